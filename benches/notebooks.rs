@@ -0,0 +1,87 @@
+//! Benchmarks for reading and clearing large notebooks, whose size is mostly image outputs
+//!
+//! The notebook is generated with one cell per megabyte of outputs, 100 by default. Set
+//! `JUV_BENCH_MB` to change its size.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::path::{Path, PathBuf};
+
+use juv::notebook::{ClearPolicy, Notebook};
+
+/// Writes a notebook with roughly `JUV_BENCH_MB` megabytes of base64 encoded images
+fn large_notebook(dir: &Path) -> PathBuf {
+    let megabytes: usize = std::env::var("JUV_BENCH_MB")
+        .ok()
+        .and_then(|mb| mb.parse().ok())
+        .unwrap_or(100);
+    let image = "iVBORw0KGgo".repeat((1 << 20) / 11);
+    let cells: Vec<_> = (0..megabytes)
+        .map(|i| {
+            serde_json::json!({
+                "cell_type": "code",
+                "id": format!("cell-{i}"),
+                "metadata": {},
+                "execution_count": i + 1,
+                "source": ["import matplotlib.pyplot as plt\n", format!("plt.plot(range({i}))")],
+                "outputs": [{
+                    "output_type": "display_data",
+                    "data": {
+                        "image/png": image,
+                        "text/plain": ["<Figure size 640x480 with 1 Axes>"],
+                    },
+                    "metadata": {},
+                }],
+            })
+        })
+        .collect();
+    let notebook = serde_json::json!({
+        "cells": cells,
+        "metadata": {},
+        "nbformat": 4,
+        "nbformat_minor": 5,
+    });
+    let path = dir.join("large.ipynb");
+    std::fs::write(&path, serde_json::to_string(&notebook).unwrap()).unwrap();
+    path
+}
+
+fn read(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = large_notebook(dir.path());
+    let mut group = c.benchmark_group("read");
+    group.sample_size(10);
+    group.bench_function("with outputs", |b| {
+        b.iter(|| Notebook::from_path(&path).unwrap())
+    });
+    group.bench_function("without outputs", |b| {
+        b.iter(|| Notebook::from_path_without_outputs(&path).unwrap())
+    });
+    group.finish();
+}
+
+/// Clears all outputs, reading the notebook with and without them
+fn clear(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = large_notebook(dir.path());
+    let output = dir.path().join("cleared.ipynb");
+    let mut group = c.benchmark_group("clear");
+    group.sample_size(10);
+    group.bench_function("with outputs", |b| {
+        b.iter(|| {
+            let mut notebook = Notebook::from_path(&path).unwrap();
+            notebook.clear_cells(ClearPolicy::default()).unwrap();
+            notebook.write(&output).unwrap();
+        })
+    });
+    group.bench_function("without outputs", |b| {
+        b.iter(|| {
+            let mut notebook = Notebook::from_path_without_outputs(&path).unwrap();
+            notebook.clear_cells(ClearPolicy::default()).unwrap();
+            notebook.write(&output).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, read, clear);
+criterion_main!(benches);
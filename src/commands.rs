@@ -1,79 +1,420 @@
-use crate::notebook::{Notebook, NotebookBuilder};
+use crate::config::Config;
+use crate::lock::NotebookLock;
+use crate::notebook::{
+    is_notebook_path, join_source, split_source, ClearOutputs, ClearPolicy, Notebook,
+    NotebookBuilder,
+};
 use crate::printer::Printer;
-use crate::script::Runtime;
+use crate::script::{
+    prepare_capture_script, prepare_connect_script, prepare_exec_script, validate_run_template,
+    ExportFormat, Runtime, ServerOptions, Timeouts, TIMEOUT_EXIT_CODE,
+};
 use anyhow::{bail, Result};
 use once_cell::sync::Lazy;
 use owo_colors::OwoColorize;
 use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::fmt::Write as _;
-use std::io::{self, BufWriter, Write};
+use std::io::{self, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use tempfile::NamedTempFile;
 
-#[allow(clippy::too_many_arguments)]
-pub fn run(
-    printer: &Printer,
-    path: &Path,
-    with: &[String],
-    python: Option<&str>,
-    jupyter: Option<&str>,
-    jupyter_args: &[String],
-    no_project: bool,
-    managed: bool,
-    dry_run: bool,
-) -> Result<()> {
-    let runtime: Runtime = jupyter.unwrap_or("lab").parse()?;
+/// How uv finds the project (or workspace) to run a notebook in
+#[derive(Default)]
+pub enum Project {
+    /// Discover the project from the working directory
+    #[default]
+    Discover,
+    /// Use the project in the given directory
+    Path(String),
+    /// Don't use a project
+    None,
+}
+
+impl Project {
+    pub fn new(project: Option<&Path>, no_project: bool) -> Result<Self> {
+        Ok(match project {
+            // Commands may run uv from another directory, so resolve the path up front
+            Some(project) => Project::Path(std::path::absolute(project)?.to_string_lossy().into()),
+            None if no_project => Project::None,
+            None => Project::Discover,
+        })
+    }
+
+    /// Resolves a relative notebook path that doesn't exist from the working directory
+    /// against the project's root instead, if it exists there
+    ///
+    /// This lets `juv run notebooks/a.ipynb` work from any subdirectory of a workspace.
+    pub fn resolve_notebook(&self, path: &Path) -> Result<PathBuf> {
+        if path.is_absolute() || path.exists() {
+            return Ok(path.to_path_buf());
+        }
+        let root = match self {
+            Project::Discover => find_project_root(&std::env::current_dir()?),
+            Project::Path(project) => Some(PathBuf::from(project)),
+            Project::None => None,
+        };
+        Ok(root
+            .map(|root| root.join(path))
+            .filter(|path| path.exists())
+            .unwrap_or_else(|| path.to_path_buf()))
+    }
+
+    /// Provides the arguments for `uv run`
+    fn args(&self) -> Vec<&str> {
+        match self {
+            Project::Discover => vec![],
+            Project::Path(path) => vec!["--project", path],
+            Project::None => vec!["--no-project"],
+        }
+    }
+}
+
+/// Finds the root of the uv workspace (or project) containing `dir`
+///
+/// That's the nearest directory with a `uv.lock`, since workspace members share their root's
+/// lock, or else the nearest with a `pyproject.toml`.
+fn find_project_root(dir: &Path) -> Option<PathBuf> {
+    let find = |file: &str| {
+        dir.ancestors()
+            .find(|dir| dir.join(file).is_file())
+            .map(Path::to_path_buf)
+    };
+    find("uv.lock").or_else(|| find("pyproject.toml"))
+}
+
+/// Options for [`run`]
+pub struct RunOptions<'a> {
+    pub with: &'a [String],
+    pub with_requirements: &'a [PathBuf],
+    pub python: Option<&'a str>,
+    pub python_preference: Option<&'a str>,
+    /// The runtime specifier (e.g., `lab` or `notebook@6`)
+    pub jupyter: Option<&'a str>,
+    pub jupyter_args: &'a [String],
+    pub server_options: ServerOptions,
+    pub auto_port: bool,
+    pub browser: bool,
+    pub trust: bool,
+    pub env: &'a [(String, String)],
+    pub env_file: Option<&'a Path>,
+    pub project: Project,
+    pub managed: bool,
+    pub check_runtime: bool,
+    pub dry_run: bool,
+    pub detach: bool,
+    pub capture_url: bool,
+    pub setup: bool,
+    pub run_template: Option<&'a Path>,
+    pub open: Option<&'a Path>,
+    pub quiet: bool,
+    /// Pass `--offline` to uv
+    pub offline: bool,
+    pub remember_runtime: bool,
+}
+
+pub fn run(printer: &Printer, path: &Path, options: RunOptions) -> Result<()> {
+    let RunOptions {
+        with,
+        with_requirements,
+        python,
+        python_preference,
+        jupyter,
+        jupyter_args,
+        mut server_options,
+        auto_port,
+        browser,
+        trust,
+        env,
+        env_file,
+        project,
+        managed,
+        check_runtime,
+        dry_run,
+        detach,
+        capture_url,
+        setup,
+        run_template,
+        open,
+        quiet,
+        offline,
+        remember_runtime,
+    } = options;
+    let path = &project.resolve_notebook(path)?;
     let notebook = Notebook::from_path(path)?;
+    let remembered = remembered_runtime(&notebook);
+    let specifier = jupyter.or(remembered.as_deref()).unwrap_or("lab");
+    let runtime = resolve_runtime(specifier)?;
+    if remember_runtime && !dry_run && remembered.as_deref() != Some(specifier) {
+        ensure_writable(path)?;
+        let _lock = NotebookLock::acquire(path)?;
+        let mut nb = Notebook::from_path(path)?;
+        remember(&mut nb, specifier);
+        nb.write(path)?;
+    }
+    let run_template = match run_template {
+        Some(file) => Some(file.to_path_buf()),
+        None => Config::discover(&std::env::current_dir()?)?.run_template,
+    };
+    let run_template = match run_template.filter(|_| runtime.is_jupyter()) {
+        Some(file) => {
+            let template = std::fs::read_to_string(&file).map_err(|err| {
+                anyhow::anyhow!("Failed to read run template `{}`: {}", file.display(), err)
+            })?;
+            validate_run_template(&template)
+                .map_err(|err| anyhow::anyhow!("{} (in `{}`)", err, file.display()))?;
+            Some(template)
+        }
+        None => None,
+    };
+    let with_requirements = with_requirements
+        .iter()
+        .map(|file| {
+            if !file.is_file() {
+                bail!("`{}` does not exist", file.display());
+            }
+            Ok(std::path::absolute(file)?.to_string_lossy().into_owned())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if notebook.cells().is_empty() {
+        // Still worth opening, since the notebook can be filled in from the frontend
+        writeln!(
+            printer.stderr(),
+            "{}: `{}` has no cells",
+            "warning".yellow().bold(),
+            path.display().cyan()
+        )?;
+    }
+    // Frontends render outputs, which a downloaded notebook could use to run scripts in the
+    // browser, so make sure opening it is a conscious choice
+    if !trust && notebook.code_cells().any(has_active_outputs) {
+        writeln!(
+            printer.stderr(),
+            "{}: `{}` has outputs with JavaScript, which can be unsafe if the notebook comes from a source you don't trust. Pass `{}` to silence this warning.",
+            "warning".yellow().bold(),
+            path.display().cyan(),
+            "--trust".yellow().bold()
+        )?;
+    }
 
-    let meta = notebook.as_ref().cells.iter().find_map(|cell| {
-        if let nbformat::v4::Cell::Code { source, .. } = cell {
-            PEP723_REGEX
-                .captures(&source.join(""))
-                .and_then(|cap| cap.get(0).map(|m| m.as_str().to_string()))
-        } else {
-            None
+    // With `--open`, the server is rooted at the project and opens the given file instead
+    let open = match open {
+        Some(open) => {
+            let root = match &project {
+                Project::Path(project) => PathBuf::from(project),
+                Project::None => std::env::current_dir()?,
+                Project::Discover => {
+                    let cwd = std::env::current_dir()?;
+                    find_project_root(&cwd).unwrap_or(cwd)
+                }
+            };
+            let target = root.join(open);
+            if !target.is_file() {
+                bail!(
+                    "`{}` does not exist in `{}`",
+                    open.display(),
+                    root.display()
+                );
+            }
+            let root = std::fs::canonicalize(&root)?;
+            let Ok(relative) = std::fs::canonicalize(&target)?
+                .strip_prefix(&root)
+                .map(Path::to_path_buf)
+            else {
+                bail!("`{}` is outside of `{}`", open.display(), root.display());
+            };
+            Some((root, relative))
         }
-    });
+        None => None,
+    };
+
+    if !runtime.is_jupyter() && (server_options.port.is_some() || open.is_some()) {
+        writeln!(
+            printer.stderr(),
+            "{}: Server options and `{}` are ignored for custom runtimes",
+            "warning".yellow().bold(),
+            "--open".yellow().bold()
+        )?;
+    }
+    // Custom runtimes open the notebook by its path, wherever it lives
+    let open = open.filter(|_| runtime.is_jupyter());
+
+    if let Some(port) = server_options.port.filter(|_| runtime.is_jupyter()) {
+        // Jupyter's own error for an occupied port is easy to miss, so check up front
+        let host = server_options
+            .ip
+            .as_deref()
+            .filter(|ip| ip.parse::<std::net::IpAddr>().is_ok())
+            .unwrap_or("127.0.0.1");
+        if !is_port_available(host, port) {
+            let Some(free_port) = (port.saturating_add(1)..=u16::MAX)
+                .take(100)
+                .find(|p| is_port_available(host, *p))
+            else {
+                bail!("Port {} is already in use", port);
+            };
+            if !auto_port {
+                writeln!(
+                    printer.stderr(),
+                    "{}: Port {} is already in use. Try `{}` or pass `{}` to pick a free port.",
+                    "error".red().bold(),
+                    port.to_string().cyan(),
+                    format!("--port {}", free_port).yellow().bold(),
+                    "--auto-port".yellow().bold()
+                )?;
+                std::process::exit(1);
+            }
+            writeln!(
+                printer.stderr(),
+                "{}: Port {} is already in use, using port {} instead",
+                "warning".yellow().bold(),
+                port.to_string().cyan(),
+                free_port.to_string().cyan()
+            )?;
+            server_options.port = Some(free_port);
+        }
+    }
+
+    // Jupyter would otherwise try (and fail, or hang) to open a browser on a headless machine
+    if runtime.is_jupyter() && !server_options.no_browser && !browser && is_headless() {
+        writeln!(
+            printer.stderr(),
+            "No display found, so the front end won't be opened in a browser. Pass `{}` to open it anyway.",
+            "--browser".yellow().bold()
+        )?;
+        server_options.no_browser = true;
+    }
+
+    let meta = find_inline_metadata(&notebook);
+    if let Some(meta) = &meta {
+        warn_shadowed_dependencies(printer, meta, with)?;
+    }
+    ensure_python_satisfies(printer, python, meta.as_deref())?;
 
     // TODO: Support managed version
+    if check_runtime {
+        let requirements = runtime.requirements();
+        if !requirements.is_empty() {
+            ensure_installable(printer, &requirements, python, offline)?;
+        }
+    }
     let with_args = runtime.with_args();
-    let script = runtime.prepare_run_script(path, meta.as_deref(), managed, jupyter_args);
+    let jupyter_args = {
+        let mut args = runtime.server_args(&server_options);
+        if let Some((root, _)) = &open {
+            args.push(runtime.root_dir_arg(root));
+        } else if runtime.is_jupyter() {
+            if let Some(dir) = server_root(path)? {
+                args.push(runtime.root_dir_arg(&dir));
+            }
+        }
+        args.extend(jupyter_args.iter().cloned());
+        args
+    };
+    let file_to_open = open.as_ref().map_or(path, |(_, relative)| relative);
+    let script = runtime.prepare_run_script(
+        file_to_open,
+        meta.as_deref(),
+        managed,
+        setup,
+        &jupyter_args,
+        run_template.as_deref(),
+    );
 
     let args = {
-        let mut args = vec!["run", "--with", with_args.as_ref()];
-        if no_project {
-            args.push("--no-project");
+        let mut args = vec!["run"];
+        // Only silences uv itself, the server's output (and its URL) still comes through
+        if quiet {
+            args.push("--quiet");
         }
+        if offline {
+            args.push("--offline");
+        }
+        if let Some(with_args) = &with_args {
+            args.extend(["--with", with_args.as_str()]);
+        }
+        args.extend(project.args());
         if let Some(python) = python {
             args.push("--python");
             args.push(python);
         }
+        if let Some(python_preference) = python_preference {
+            args.push("--python-preference");
+            args.push(python_preference);
+        }
         for with_item in with {
             args.push("--with");
             args.push(with_item);
         }
+        // The notebook's inline dependencies come from the script's metadata, so these
+        // are layered on top of them rather than replacing them
+        for file in &with_requirements {
+            args.push("--with-requirements");
+            args.push(file);
+        }
         args.push("-"); // stdin
         args
     };
 
+    let envs = resolve_env(env, env_file)?;
+
     if dry_run {
         println!("uv {}", args.join(" "));
         println!("{}", script);
         return Ok(());
     }
+    check_uv_version(printer, MIN_UV_VERSION, false)?;
+
+    if detach {
+        return spawn_detached(printer, path, &args, envs, &script);
+    }
 
+    let output = || {
+        if capture_url {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        }
+    };
     let mut child = Command::new("uv")
         .args(&args)
+        .envs(envs)
         .stdin(Stdio::piped())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
+        .stdout(output())
+        .stderr(output())
         .spawn()?;
 
     let stdin = child.stdin.as_mut().expect("Failed to open stdin");
     stdin.write_all(script.as_bytes())?;
 
+    let mut tees = vec![];
+    if capture_url {
+        // The server shuts itself down on Ctrl-C, so stay alive to pass through its
+        // final output rather than exiting with it.
+        ctrlc::set_handler(|| {})?;
+        let reported = Arc::new(AtomicBool::new(false));
+        if let Some(stdout) = child.stdout.take() {
+            tees.push(tee_server_output(
+                printer,
+                stdout,
+                io::stdout(),
+                reported.clone(),
+            ));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tees.push(tee_server_output(printer, stderr, io::stderr(), reported));
+        }
+    }
+
     let status = child.wait()?;
+    for tee in tees {
+        let _ = tee.join();
+    }
     if !status.success() {
         writeln!(
             printer.stderr(),
@@ -87,46 +428,588 @@ pub fn run(
     Ok(())
 }
 
-pub fn exec(
-    _printer: &Printer,
+/// Finds the directory to root a Jupyter server at for it to open the notebook
+///
+/// The server can only open files below its root (the current directory by default), so it
+/// is served from the notebook's directory when the notebook lives elsewhere.
+fn server_root(path: &Path) -> Result<Option<PathBuf>> {
+    let notebook_path = std::path::absolute(path)?;
+    if notebook_path.starts_with(std::env::current_dir()?) {
+        return Ok(None);
+    }
+    Ok(notebook_path.parent().map(Path::to_path_buf))
+}
+
+/// How long to wait for a detached server to report its URL
+const DETACH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Starts the Jupyter server in the background, reporting its URL and PID once it's up.
+///
+/// The server's output goes to a log file in `<temp dir>/juv/logs/` and the process is
+/// moved to its own process group, so it outlives juv and ignores the terminal's Ctrl-C.
+fn spawn_detached(
+    printer: &Printer,
     path: &Path,
-    python: Option<&str>,
-    with: &[String],
-    quiet: bool,
+    args: &[&str],
+    envs: Vec<(String, String)>,
+    script: &str,
+) -> Result<()> {
+    let log_dir = std::env::temp_dir().join("juv").join("logs");
+    std::fs::create_dir_all(&log_dir)?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let (log_file, log_path) = tempfile::Builder::new()
+        .prefix(&format!("{stem}-"))
+        .suffix(".log")
+        .tempfile_in(&log_dir)?
+        .keep()?;
+
+    let mut command = Command::new("uv");
+    command
+        .args(args)
+        .envs(envs)
+        .stdin(Stdio::piped())
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file);
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    #[cfg(windows)]
+    std::os::windows::process::CommandExt::creation_flags(&mut command, 0x0000_0200); // CREATE_NEW_PROCESS_GROUP
+    let mut child = command.spawn()?;
+
+    // Closing stdin lets uv know the script is complete
+    let mut stdin = child.stdin.take().expect("Failed to open stdin");
+    stdin.write_all(script.as_bytes())?;
+    drop(stdin);
+
+    let start = Instant::now();
+    let url = loop {
+        let log = std::fs::read_to_string(&log_path).unwrap_or_default();
+        if let Some(url) = log.lines().find_map(find_server_url) {
+            break Some(url.to_string());
+        }
+        if let Some(status) = child.try_wait()? {
+            writeln!(
+                printer.stderr(),
+                "{}: Jupyter server exited with exit code {}. See `{}` for details.",
+                "error".red().bold(),
+                status.code().unwrap_or(-1),
+                log_path.display().cyan()
+            )?;
+            std::process::exit(1);
+        }
+        if start.elapsed() > DETACH_TIMEOUT {
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(250));
+    };
+
+    match url {
+        Some(url) => writeln!(
+            printer.stderr(),
+            "Jupyter server running at {}",
+            url.cyan().bold()
+        )?,
+        None => writeln!(
+            printer.stderr(),
+            "{}: Timed out waiting for the Jupyter server URL",
+            "warning".yellow().bold()
+        )?,
+    }
+    writeln!(printer.stderr(), "PID: {}", child.id().to_string().cyan())?;
+    writeln!(printer.stderr(), "Logs: {}", log_path.display().cyan())?;
+    writeln!(
+        printer.stderr(),
+        "Stop the server with `{}`",
+        format!("kill {}", child.id()).yellow().bold()
+    )?;
+
+    Ok(())
+}
+
+/// Passes a child process's output through to `sink`, calling `on_chunk` with each chunk
+fn tee(
+    mut source: impl Read + Send + 'static,
+    mut sink: impl Write + Send + 'static,
+    mut on_chunk: impl FnMut(&[u8]) + Send + 'static,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut buf = [0; 8192];
+        while let Ok(n @ 1..) = source.read(&mut buf) {
+            let _ = sink.write_all(&buf[..n]);
+            let _ = sink.flush();
+            on_chunk(&buf[..n]);
+        }
+    })
+}
+
+/// Passes a server's output through to `sink`, reprinting the server's URL the first
+/// time it shows up so it doesn't get lost in the server's logs.
+fn tee_server_output(
+    printer: &Printer,
+    source: impl Read + Send + 'static,
+    sink: impl Write + Send + 'static,
+    reported: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<()> {
+    let printer = *printer;
+    let mut pending = vec![];
+    tee(source, sink, move |chunk| {
+        pending.extend_from_slice(chunk);
+        while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let text = String::from_utf8_lossy(&line);
+            if let Some(url) = find_server_url(&text) {
+                if !reported.swap(true, Ordering::Relaxed) {
+                    let _ = writeln!(
+                        printer.stderr(),
+                        "\n    {} {}\n",
+                        "Jupyter server running at".bold(),
+                        url.cyan().bold()
+                    );
+                }
+            }
+        }
+    })
+}
+
+/// Finds the URL a Jupyter server reports it is reachable at in a line of its output
+fn find_server_url(line: &str) -> Option<&str> {
+    SERVER_URL_REGEX.find(line).map(|m| m.as_str())
+}
+
+static SERVER_URL_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"https?://(\S+[?&]token=|localhost|127\.0\.0\.1)\S*").unwrap());
+
+/// Execution details recorded by `juv exec --record-metadata`
+///
+/// Mirrors the record papermill writes to `metadata.papermill`, so tooling that reads
+/// papermill-executed notebooks can also consume juv-executed ones.
+#[derive(Serialize)]
+struct ExecutionRecord {
+    start_time: String,
+    end_time: String,
+    duration: f64,
+    exception: Option<bool>,
+    input_path: String,
+    output_path: String,
+    parameters: serde_json::Map<String, serde_json::Value>,
+    environment_variables: serde_json::Map<String, serde_json::Value>,
+    version: &'static str,
+}
+
+/// Finds where `--output-dir` puts the export of a notebook
+///
+/// The notebook's path relative to the working directory is kept, so notebooks with the
+/// same name in different directories don't overwrite each other.
+fn mirrored_output(path: &Path, output_dir: &Path, format: ExportFormat) -> Result<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    let Ok(relative) = path.strip_prefix(&cwd) else {
+        bail!(
+            "`{}` is outside the working directory, so it can't be mirrored into `{}` (use `--output` instead)",
+            path.display(),
+            output_dir.display()
+        );
+    };
+    let output = std::path::absolute(output_dir)?
+        .join(relative)
+        .with_extension(format.extension());
+    if output == path {
+        bail!(
+            "Exporting `{}` into `{}` would overwrite the notebook itself",
+            path.display(),
+            output_dir.display()
+        );
+    }
+    Ok(output)
+}
+
+/// Writes the notebook an exec script produced to `output` in the export format
+///
+/// With a `record`, it is stored under `metadata.papermill` of the written notebook.
+fn write_executed(
+    executed: &Path,
+    format: ExportFormat,
+    output: &Path,
+    record: Option<ExecutionRecord>,
 ) -> Result<()> {
-    let path = std::path::absolute(path)?;
-    let mut args = vec!["run", "-"];
+    let mut nb = Notebook::from_path(executed)?;
+    if let Some(record) = record {
+        nb.as_mut()
+            .metadata
+            .additional
+            .insert("papermill".to_string(), serde_json::to_value(record)?);
+    }
+    // The same rendering as `juv cat --outputs`, so both agree on what a notebook looks like
+    let render_options = RenderOptions {
+        outputs: true,
+        ..RenderOptions::default()
+    };
+    let mut contents = vec![];
+    match format {
+        ExportFormat::Notebook => return nb.write(output),
+        ExportFormat::Script => write_script(&mut contents, nb.as_ref(), render_options)?,
+        ExportFormat::Markdown => write_markdown(&mut contents, &nb, render_options)?,
+        ExportFormat::Html => unreachable!("HTML is exported by nbconvert"),
+    }
+    std::fs::write(output, contents)?;
+    Ok(())
+}
+
+/// Options for [`exec`]
+#[derive(Default)]
+pub struct ExecOptions<'a> {
+    pub python: Option<&'a str>,
+    pub python_preference: Option<&'a str>,
+    pub with: &'a [String],
+    pub env: &'a [(String, String)],
+    pub env_file: Option<&'a Path>,
+    pub export: Option<ExportFormat>,
+    pub output: Option<&'a Path>,
+    pub output_dir: Option<&'a Path>,
+    pub record_metadata: bool,
+    pub no_output_timeout: Option<Duration>,
+    pub cell_timeout: Option<Duration>,
+    pub timeout: Option<Duration>,
+    /// The mime type of the last cell's output to print
+    pub capture_last: Option<&'a str>,
+    /// The connection file of a running kernel to execute in
+    pub connect: Option<&'a Path>,
+    pub params_file: Option<&'a Path>,
+    pub params: &'a [(String, String)],
+    pub kernel_name: Option<&'a str>,
+    pub project: Project,
+    pub quiet: bool,
+    /// Pass `--offline` to uv
+    pub offline: bool,
+}
+
+pub fn exec(printer: &Printer, path: &Path, options: ExecOptions) -> Result<()> {
+    let ExecOptions {
+        python,
+        python_preference,
+        with,
+        env,
+        env_file,
+        export,
+        output,
+        output_dir,
+        record_metadata,
+        no_output_timeout,
+        cell_timeout,
+        timeout,
+        capture_last,
+        connect,
+        params_file,
+        params,
+        kernel_name,
+        project,
+        quiet,
+        offline,
+    } = options;
+    let path = std::path::absolute(project.resolve_notebook(path)?)?;
+    // Mirroring into a directory without a format writes the executed notebook
+    let export = export.or(output_dir.map(|_| ExportFormat::Notebook));
+    let mut parameters = match params_file {
+        Some(file) => read_params_file(file)?,
+        None => serde_json::Map::new(),
+    };
+    for (key, value) in params {
+        parameters.insert(key.clone(), parse_param_value(value));
+    }
+    for key in parameters.keys() {
+        if !is_python_identifier(key) {
+            bail!("Parameter `{}` is not a valid Python identifier", key);
+        }
+    }
+    let kernel = connect.is_some() || export.is_some() || capture_last.is_some();
+    if cell_timeout.is_some() && !kernel {
+        bail!(
+            "`--cell-timeout` requires running the notebook in a kernel (with `--to`, `--capture-last`, or `--connect`)"
+        );
+    }
+    // Measured from launch, so setting up the environment counts against the limit
+    let timeouts = Timeouts {
+        cell: cell_timeout,
+        notebook: timeout.map(|timeout| (timeout, SystemTime::now() + timeout)),
+    };
+    if record_metadata && export != Some(ExportFormat::Notebook) {
+        bail!(
+            "`--record-metadata` requires `--to notebook` (or `--output-dir`), the record is written into the executed notebook"
+        );
+    }
+    let connect = match connect {
+        Some(connection_file) if !connection_file.is_file() => {
+            bail!("`{}` does not exist", connection_file.display());
+        }
+        Some(connection_file) => Some(std::path::absolute(connection_file)?),
+        None => None,
+    };
+    let envs = resolve_env(env, env_file)?;
+    let mut nb = Notebook::from_path(path.as_ref())?;
+    if nb.code_cells().next().is_none() {
+        writeln!(
+            printer.stderr(),
+            "{}: `{}` has no code cells, nothing to execute",
+            "warning".yellow().bold(),
+            path.display().cyan()
+        )?;
+        return Ok(());
+    }
+    // With `--connect`, the kernel's interpreter runs the notebook
+    if connect.is_none() {
+        ensure_python_satisfies(printer, python, find_inline_metadata(&nb).as_deref())?;
+    }
+
+    // The notebook on disk is left alone, the parameterized copy is what gets executed
+    let parameterized = if parameters.is_empty() {
+        None
+    } else {
+        let source = std::iter::once("# Parameters".to_string())
+            .chain(
+                parameters
+                    .iter()
+                    .map(|(key, value)| format!("{key} = {}", python_literal(value))),
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !nb.inject_parameters(&source) {
+            writeln!(
+                printer.stderr(),
+                "{}: `{}` has no cell tagged `{}`, injecting parameters at the top",
+                "warning".yellow().bold(),
+                path.display().cyan(),
+                "parameters".cyan()
+            )?;
+        }
+        // Only a kernel reads the notebook from disk, a plain script is rendered from `nb`
+        if kernel {
+            // Next to the notebook, so relative paths resolve the same way
+            let file = tempfile::Builder::new()
+                .suffix(".ipynb")
+                .tempfile_in(path.parent().expect("path must have a parent"))?;
+            nb.write(file.path())?;
+            Some(file)
+        } else {
+            None
+        }
+    };
+    let notebook_path = parameterized
+        .as_ref()
+        .map_or(path.as_path(), NamedTempFile::path);
+
+    let mut args = vec!["run"];
     if quiet {
         args.push("--quiet");
     }
+    if offline {
+        args.push("--offline");
+    }
+    args.extend(project.args());
     if let Some(python) = python {
         args.push("--python");
         args.push(python);
     }
+    if let Some(python_preference) = python_preference {
+        args.push("--python-preference");
+        args.push(python_preference);
+    }
     for with_item in with {
         args.push("--with");
         args.push(with_item);
     }
 
+    let export = match (export, output, output_dir) {
+        (Some(format), Some(output), _) => Some((format, std::path::absolute(output)?)),
+        (Some(format), None, Some(output_dir)) => {
+            let output = mirrored_output(&path, output_dir, format)?;
+            std::fs::create_dir_all(output.parent().expect("path must have a parent"))?;
+            Some((format, output))
+        }
+        // Never the notebook itself, which stays as it was
+        (Some(ExportFormat::Notebook), None, None) => Some((
+            ExportFormat::Notebook,
+            path.with_extension("executed.ipynb"),
+        )),
+        (Some(format), None, None) => Some((format, path.with_extension(format.extension()))),
+        (None, _, _) => None,
+    };
+    // Formats nbconvert doesn't export are written by juv from the executed notebook
+    let scratch = match &export {
+        Some((format, _)) if format.exporter().is_none() => Some(tempfile::tempdir()?),
+        _ => None,
+    };
+    let executed = scratch
+        .as_ref()
+        .map(|scratch| scratch.path().join("executed.ipynb"));
+
+    let echo_streams = no_output_timeout.is_some();
+    // The notebook may have been saved with a kernel that isn't installed in uv's environment
+    let kernel_name = kernel_name
+        .or_else(|| {
+            nb.as_ref()
+                .metadata
+                .kernelspec
+                .as_ref()
+                .map(|kernelspec| kernelspec.name.as_str())
+        })
+        .unwrap_or("python3");
+    let script = match (connect.as_deref(), &export, capture_last) {
+        (Some(connection_file), _, _) => {
+            // The kernel brings its own environment, so only the client is installed
+            args.extend(["--with", "jupyter_client", "--with", "nbformat"]);
+            prepare_connect_script(notebook_path, connection_file, timeouts).into_bytes()
+        }
+        (None, Some((format, output)), _) => {
+            // Execute with a kernel so the outputs can be captured and exported
+            if format.exporter().is_some() {
+                args.extend(["--with", "nbconvert", "--with", "ipykernel"]);
+            } else {
+                args.extend(["--with", "nbclient", "--with", "ipykernel"]);
+            }
+            let meta = find_inline_metadata(&nb);
+            prepare_exec_script(
+                notebook_path,
+                meta.as_deref(),
+                kernel_name,
+                *format,
+                executed.as_deref().unwrap_or(output),
+                echo_streams,
+                timeouts,
+            )
+            .into_bytes()
+        }
+        (None, None, Some(mime)) => {
+            args.extend(["--with", "nbclient", "--with", "ipykernel"]);
+            let meta = find_inline_metadata(&nb);
+            prepare_capture_script(
+                notebook_path,
+                meta.as_deref(),
+                kernel_name,
+                mime,
+                echo_streams,
+                timeouts,
+            )
+            .into_bytes()
+        }
+        (None, None, None) => {
+            let mut script = vec![];
+            write_script(&mut script, nb.as_ref(), RenderOptions::default())?;
+            script
+        }
+    };
+    args.push("-"); // stdin
+
+    let output = || {
+        if no_output_timeout.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        }
+    };
+    check_uv_version(printer, MIN_UV_VERSION, false)?;
+    let start_time = jiff::Timestamp::now();
     let mut child = Command::new("uv")
         .args(&args)
         .current_dir(path.parent().unwrap())
+        .envs(envs)
+        // Python buffers output written to a pipe, which would look like silence
+        .env("PYTHONUNBUFFERED", "1")
         .stdin(Stdio::piped())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
+        .stdout(output())
+        .stderr(output())
         .spawn()?;
 
     {
+        // Taking stdin closes it once the script is written, so uv knows it's complete
         let mut stdin = child
             .stdin
-            .as_ref()
+            .take()
             .map(BufWriter::new)
             .expect("Failed to open stdin");
-        let nb = Notebook::from_path(path.as_ref())?;
-        write_script(&mut stdin, nb.as_ref())?;
+        stdin.write_all(&script)?;
+    }
+
+    // Kernels enforce the limit themselves, stopping at the cell that ran out of time
+    let deadline = timeouts.notebook.filter(|_| !kernel);
+    let status = if no_output_timeout.is_none() && deadline.is_none() {
+        child.wait()?
+    } else {
+        let last_output = Arc::new(Mutex::new(Instant::now()));
+        let touch = |last_output: &Arc<Mutex<Instant>>| {
+            let last_output = last_output.clone();
+            move |_: &[u8]| *last_output.lock().unwrap() = Instant::now()
+        };
+        let mut tees = vec![];
+        if let Some(stdout) = child.stdout.take() {
+            tees.push(tee(stdout, io::stdout(), touch(&last_output)));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tees.push(tee(stderr, io::stderr(), touch(&last_output)));
+        }
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            let aborted = if let Some(timeout) =
+                no_output_timeout.filter(|timeout| last_output.lock().unwrap().elapsed() > *timeout)
+            {
+                Some((
+                    format!(
+                        "No output for {} seconds, aborting",
+                        timeout.as_secs().to_string().cyan()
+                    ),
+                    1,
+                ))
+            } else if let Some((timeout, _)) =
+                deadline.filter(|(_, deadline)| SystemTime::now() > *deadline)
+            {
+                Some((
+                    format!(
+                        "The notebook ran longer than {} seconds, aborting",
+                        timeout.as_secs().to_string().cyan()
+                    ),
+                    TIMEOUT_EXIT_CODE,
+                ))
+            } else {
+                None
+            };
+            if let Some((message, code)) = aborted {
+                child.kill()?;
+                child.wait()?;
+                writeln!(printer.stderr(), "{}: {}", "error".red().bold(), message)?;
+                std::process::exit(code);
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        };
+        for tee in tees {
+            let _ = tee.join();
+        }
+        status
+    };
+    let end_time = jiff::Timestamp::now();
+
+    // Missing when the script failed before it got to write the executed notebook
+    if let (Some((format, output)), Some(executed)) = (&export, &executed) {
+        if executed.is_file() {
+            let record = record_metadata.then(|| ExecutionRecord {
+                start_time: start_time.to_string(),
+                end_time: end_time.to_string(),
+                duration: end_time.duration_since(start_time).as_secs_f64(),
+                exception: (!status.success()).then_some(true),
+                input_path: path.to_string_lossy().into_owned(),
+                output_path: output.to_string_lossy().into_owned(),
+                parameters,
+                environment_variables: serde_json::Map::new(),
+                version: env!("CARGO_PKG_VERSION"),
+            });
+            write_executed(executed, *format, output, record)?;
+        }
+    }
+
+    // The script already explained which cell ran out of time
+    if status.code() == Some(TIMEOUT_EXIT_CODE) && kernel {
+        std::process::exit(TIMEOUT_EXIT_CODE);
     }
 
-    let status = child.wait()?;
     if !status.success() {
         println!(
             "{}: uv command failed with exit code {}",
@@ -136,10 +1019,91 @@ pub fn exec(
         std::process::exit(1);
     }
 
+    if let Some((_, output)) = export {
+        writeln!(printer.stderr(), "Wrote `{}`", output.display().cyan())?;
+    }
+
+    Ok(())
+}
+
+/// Executes a notebook and opens the result, rendered as HTML, in the browser
+///
+/// The HTML goes to `<temp dir>/juv/previews/`, and the notebook itself is left untouched.
+/// Only the environment related `options` apply, since the export is decided here.
+pub fn preview(printer: &Printer, path: &Path, options: ExecOptions, no_open: bool) -> Result<()> {
+    let path = options.project.resolve_notebook(path)?;
+    let preview_dir = std::env::temp_dir().join("juv").join("previews");
+    std::fs::create_dir_all(&preview_dir)?;
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let (_, html) = tempfile::Builder::new()
+        .prefix(&format!("{stem}-"))
+        .suffix(".html")
+        .tempfile_in(&preview_dir)?
+        .keep()?;
+
+    exec(
+        printer,
+        &path,
+        ExecOptions {
+            export: Some(ExportFormat::Html),
+            output: Some(&html),
+            ..options
+        },
+    )?;
+
+    if no_open {
+        println!("{}", html.display());
+        return Ok(());
+    }
+    if is_headless() {
+        writeln!(
+            printer.stderr(),
+            "{}: No display found, open `{}` in a browser to view the notebook",
+            "warning".yellow().bold(),
+            html.display().cyan()
+        )?;
+        return Ok(());
+    }
+    open::that(&html)
+        .map_err(|err| anyhow::anyhow!("Failed to open `{}` in a browser: {}", html.display(), err))
+}
+
+/// Checks that creating a notebook at `path` won't overwrite an existing file
+fn ensure_new_notebook(path: &Path) -> Result<()> {
+    if path.exists() {
+        bail!(
+            "`{}` already exists. Pass `--force` to overwrite it.",
+            path.display()
+        );
+    }
     Ok(())
 }
 
-pub fn init(printer: &Printer, path: Option<&Path>, python: Option<&str>) -> Result<()> {
+#[allow(clippy::too_many_arguments)]
+pub fn init(
+    printer: &Printer,
+    path: Option<&Path>,
+    python: Option<&str>,
+    python_preference: Option<&str>,
+    kernel: Option<&str>,
+    force: bool,
+    git: bool,
+    offline: bool,
+) -> Result<()> {
+    check_uv_version(printer, MIN_UV_VERSION, false)?;
+    if path == Some(Path::new("-")) {
+        // Write the notebook to stdout instead of creating a file
+        let nb = new_notebook_with_inline_metadata(
+            &std::env::current_dir()?,
+            python,
+            python_preference,
+            kernel,
+            offline,
+        )?;
+        io::stdout().write_all(nb.to_formatted_json()?.as_bytes())?;
+        return Ok(());
+    }
+
     let path = match path {
         Some(p) => p.to_path_buf(),
         None => get_first_non_conflicting_untitled_ipybnb(&std::env::current_dir()?)?,
@@ -157,49 +1121,253 @@ pub fn init(printer: &Printer, path: Option<&Path>, python: Option<&str>) -> Res
         std::process::exit(1);
     }
 
-    let nb = new_notebook_with_inline_metadata(dir, python)?;
-    std::fs::write(&path, serde_json::to_string_pretty(nb.as_ref())?)?;
+    if !force {
+        ensure_new_notebook(&path)?;
+    }
+
+    let nb = new_notebook_with_inline_metadata(dir, python, python_preference, kernel, offline)?;
+    nb.write(&path)?;
 
     writeln!(
         printer.stdout(),
         "Initialized notebook at `{}`",
         path.strip_prefix(dir)?.display().cyan()
     )?;
+
+    if git {
+        ensure_checkpoints_ignored(printer, dir)?;
+    }
     Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn add(
-    printer: &Printer,
-    path: &Path,
-    packages: &[String],
-    requirements: Option<&Path>,
-    extras: &[String],
-    tag: Option<&str>,
-    branch: Option<&str>,
-    rev: Option<&str>,
-    editable: bool,
-) -> Result<()> {
-    let mut nb = Notebook::from_path(path)?;
+/// Adds `.ipynb_checkpoints/` to the `.gitignore` of the git repository containing `dir`
+///
+/// Does nothing outside of a git repository or if the entry already exists.
+fn ensure_checkpoints_ignored(printer: &Printer, dir: &Path) -> Result<()> {
+    let Some(root) = dir.ancestors().find(|dir| dir.join(".git").exists()) else {
+        return Ok(());
+    };
+    let gitignore = root.join(".gitignore");
+    let contents = match std::fs::read_to_string(&gitignore) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let ignored = contents.lines().any(|line| {
+        matches!(
+            line.trim()
+                .trim_start_matches("**/")
+                .trim_start_matches('/'),
+            ".ipynb_checkpoints" | ".ipynb_checkpoints/"
+        )
+    });
+    if ignored {
+        return Ok(());
+    }
 
-    for cell in nb.as_mut().cells.iter_mut() {
-        match cell {
-            nbformat::v4::Cell::Code { source, .. } if PEP723_REGEX.is_match(&source.join("")) => {
-                let temp_file = tempfile::Builder::new()
-                    .suffix(".py")
-                    .tempfile_in(path.parent().unwrap())?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&gitignore)?;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        file.write_all(b"\n")?;
+    }
+    file.write_all(b".ipynb_checkpoints/\n")?;
+    writeln!(
+        printer.stdout(),
+        "Added `{}` to `{}`",
+        ".ipynb_checkpoints/".cyan(),
+        gitignore.display().cyan()
+    )?;
+    Ok(())
+}
 
-                std::fs::write(temp_file.path(), source.join("").trim())?;
+/// Options for [`add`]
+#[derive(Default)]
+pub struct AddOptions<'a> {
+    pub packages: &'a [String],
+    pub requirements: Option<&'a Path>,
+    pub extras: &'a [String],
+    pub tag: Option<&'a str>,
+    pub branch: Option<&'a str>,
+    pub rev: Option<&'a str>,
+    pub editable: bool,
+    pub bounds: Option<&'a str>,
+    pub upgrade: bool,
+    pub constraints: &'a [PathBuf],
+    pub overrides: &'a [PathBuf],
+    pub update_lock: bool,
+    pub strict: bool,
+    pub dry_run: bool,
+    pub no_python_bump: bool,
+    /// The project to resolve the notebook's path from (`uv add --script` ignores projects)
+    pub project: Project,
+    /// Pass `--offline` to uv
+    pub offline: bool,
+}
 
-                let mut command = Command::new("uv");
-                command.arg("add").arg("--script").arg(temp_file.path());
+pub fn add(printer: &Printer, path: &Path, options: AddOptions) -> Result<()> {
+    let AddOptions {
+        packages,
+        requirements,
+        extras,
+        tag,
+        branch,
+        rev,
+        editable,
+        bounds,
+        upgrade,
+        constraints,
+        overrides,
+        update_lock,
+        strict,
+        dry_run,
+        no_python_bump,
+        project,
+        offline,
+    } = options;
+    let path = &project.resolve_notebook(path)?;
+    for file in constraints.iter().chain(overrides) {
+        if !file.is_file() {
+            bail!("`{}` does not exist", file.display());
+        }
+    }
 
-                if editable {
-                    command.arg("--editable");
+    // Fail before resolving, which can take a while, rather than after
+    ensure_writable(path)?;
+
+    // A local package enclosing the notebook would have its environment include itself,
+    // which uv reports in a way that is hard to trace back
+    let notebook_path = std::fs::canonicalize(path)?;
+    for package in packages {
+        if !looks_like_path(package) || !Path::new(package).is_dir() {
+            continue;
+        }
+        if !notebook_path.starts_with(std::fs::canonicalize(package)?) {
+            continue;
+        }
+        if strict {
+            bail!(
+                "`{}` contains the notebook `{}` itself",
+                package,
+                path.display()
+            );
+        }
+        writeln!(
+            printer.stderr(),
+            "{}: `{}` contains the notebook `{}` itself, which can make resolving it fail",
+            "warning".yellow().bold(),
+            package.cyan(),
+            path.display().cyan()
+        )?;
+    }
+
+    let _lock = NotebookLock::acquire(path)?;
+    let mut nb = Notebook::from_path(path)?;
+    let dir = std::path::absolute(path)?
+        .parent()
+        .expect("path must have a parent")
+        .to_path_buf();
+
+    // Re-adding a dependency without a version would have uv rewrite its constraint, so
+    // skip those unless `--upgrade` asks for exactly that
+    let meta = find_inline_metadata(&nb);
+    let requires_python = meta.as_deref().and_then(script_requires_python);
+    let existing: Vec<String> = meta
+        .as_deref()
+        .map(script_dependencies)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|dependency| requirement_name(dependency))
+        .collect();
+    let mut upgrades = vec![];
+    let mut to_add: Vec<&String> = vec![];
+    for package in packages {
+        match requirement_name(package) {
+            Some(name) if existing.contains(&name) && upgrade => upgrades.push(name),
+            Some(name) if existing.contains(&name) && is_bare_requirement(package) => {
+                writeln!(
+                    printer.stderr(),
+                    "{}: `{}` is already a dependency. Pass `{}` to update it.",
+                    "warning".yellow().bold(),
+                    package.cyan(),
+                    "--upgrade".yellow().bold()
+                )?;
+                continue;
+            }
+            _ => {}
+        }
+        to_add.push(package);
+    }
+    let packages = to_add;
+    if packages.is_empty() && requirements.is_none() {
+        return Ok(());
+    }
+
+    let mut lock = nb
+        .as_ref()
+        .metadata
+        .additional
+        .get(LOCK_METADATA_KEY)
+        .and_then(|lock| lock.as_str())
+        .map(str::to_string);
+    let previous_lock = lock.clone();
+    // The lock follows from the dependencies, so there's nothing more to preview
+    let update_lock = (update_lock || lock.is_some()) && !dry_run;
+    let min_uv_version = if update_lock {
+        MIN_UV_VERSION_FOR_LOCK
+    } else {
+        MIN_UV_VERSION
+    };
+    check_uv_version(printer, min_uv_version, strict)?;
+
+    let colocate =
+        requirements.is_some() || packages.iter().any(|package| looks_like_path(package));
+    for cell in nb.cells_mut() {
+        match cell {
+            nbformat::v4::Cell::Code { source, .. }
+                if PEP723_REGEX.is_match(&join_source(source)) =>
+            {
+                // uv records local paths relative to the script, so those need it in place
+                let temp_file = if colocate {
+                    tempfile::Builder::new().suffix(".py").tempfile_in(&dir)?
+                } else {
+                    temp_file_near(&dir, ".py")?
+                };
+
+                std::fs::write(temp_file.path(), join_source(source).trim())?;
+
+                let mut command = Command::new("uv");
+                // Run from the notebook's directory so local paths are recorded relative to it
+                command
+                    .current_dir(&dir)
+                    .arg("add")
+                    .arg("--script")
+                    .arg(temp_file.path());
+                if offline {
+                    command.arg("--offline");
+                }
+
+                if editable {
+                    command.arg("--editable");
                 }
 
                 if let Some(requirements) = requirements {
-                    command.arg("--requirements").arg(requirements);
+                    command
+                        .arg("--requirements")
+                        .arg(std::path::absolute(requirements)?);
+                }
+
+                for constraint in constraints {
+                    command
+                        .arg("--constraints")
+                        .arg(std::path::absolute(constraint)?);
+                }
+
+                for r#override in overrides {
+                    command
+                        .arg("--overrides")
+                        .arg(std::path::absolute(r#override)?);
                 }
 
                 if let Some(tag) = tag {
@@ -214,11 +1382,21 @@ pub fn add(
                     command.arg("--rev").arg(rev);
                 }
 
+                if let Some(bounds) = bounds {
+                    command.arg("--bounds").arg(bounds);
+                }
+
                 for extra in extras {
                     command.arg("--extra").arg(extra);
                 }
 
-                command.args(packages);
+                for name in &upgrades {
+                    command.arg("--upgrade-package").arg(name);
+                }
+
+                for package in &packages {
+                    command.arg(local_requirement(package, &dir)?);
+                }
 
                 let output = command.output()?;
 
@@ -227,12 +1405,34 @@ pub fn add(
                     anyhow::bail!("uv command failed: {}", stderr);
                 }
 
+                if update_lock {
+                    // uv keeps the lock next to the script, and starts from it when relocking
+                    let lock_path = PathBuf::from(format!("{}.lock", temp_file.path().display()));
+                    if let Some(lock) = &lock {
+                        std::fs::write(&lock_path, lock)?;
+                    }
+                    let mut command = Command::new("uv");
+                    command
+                        .current_dir(&dir)
+                        .arg("lock")
+                        .arg("--script")
+                        .arg(temp_file.path());
+                    if offline {
+                        command.arg("--offline");
+                    }
+                    let output = command.output();
+                    let contents = std::fs::read_to_string(&lock_path);
+                    let _ = std::fs::remove_file(&lock_path);
+                    let output = output?;
+                    if !output.status.success() {
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        anyhow::bail!("uv command failed: {}", stderr);
+                    }
+                    lock = Some(contents?);
+                }
+
                 let contents = std::fs::read_to_string(temp_file.path())?;
-                *source = contents
-                    .trim()
-                    .split_inclusive('\n')
-                    .map(|s| s.to_string())
-                    .collect();
+                *source = split_source(contents.trim());
 
                 break;
             }
@@ -240,26 +1440,352 @@ pub fn add(
         }
     }
 
-    std::fs::write(path, serde_json::to_string_pretty(nb.as_ref())?)?;
+    if no_python_bump {
+        let updated = find_inline_metadata(&nb)
+            .as_deref()
+            .and_then(script_requires_python);
+        if raises_requires_python(requires_python.as_deref(), updated.as_deref()) {
+            bail!(
+                "Adding the dependencies would raise `requires-python` from `{}` to `{}`",
+                requires_python.as_deref().unwrap_or("any"),
+                updated.as_deref().unwrap_or("any")
+            );
+        }
+    }
+
+    if dry_run {
+        // uv only rewrote the temporary copy, so the notebook itself is untouched
+        let updated = find_inline_metadata(&nb);
+        return report_planned_dependencies(
+            printer,
+            path,
+            meta.as_deref(),
+            updated.as_deref(),
+            previous_lock.as_deref(),
+        );
+    }
+
+    if let Some(lock) = &lock {
+        nb.as_mut()
+            .metadata
+            .additional
+            .insert(LOCK_METADATA_KEY.to_string(), lock.clone().into());
+    }
+    nb.write(path)?;
     writeln!(printer.stderr(), "Updated `{}`", path.display().cyan())?;
+    let updated_meta = find_inline_metadata(&nb);
+    print_dep_diff(
+        printer,
+        &meta.as_deref().map(script_dependencies).unwrap_or_default(),
+        &updated_meta
+            .as_deref()
+            .map(script_dependencies)
+            .unwrap_or_default(),
+        previous_lock.as_deref(),
+        lock.as_deref(),
+    )?;
+
+    // A new dependency can raise the minimum Python, which is easy to miss in the diff
+    let updated = updated_meta.as_deref().and_then(script_requires_python);
+    if updated != requires_python {
+        if let Some(updated) = updated {
+            match requires_python {
+                Some(previous) => writeln!(
+                    printer.stderr(),
+                    "Changed `requires-python` from `{}` to `{}`",
+                    previous.cyan(),
+                    updated.cyan()
+                )?,
+                None => writeln!(
+                    printer.stderr(),
+                    "Set `requires-python` to `{}`",
+                    updated.cyan()
+                )?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints how `juv add --dry-run` would change a notebook's inline metadata
+fn report_planned_dependencies(
+    printer: &Printer,
+    path: &Path,
+    before: Option<&str>,
+    after: Option<&str>,
+    lock: Option<&str>,
+) -> Result<()> {
+    let before_dependencies = before.map(script_dependencies).unwrap_or_default();
+    let after_dependencies = after.map(script_dependencies).unwrap_or_default();
+    let before_requires_python = before.and_then(script_requires_python);
+    let after_requires_python = after.and_then(script_requires_python);
+    if before_dependencies == after_dependencies && before_requires_python == after_requires_python
+    {
+        writeln!(
+            printer.stderr(),
+            "No changes to `{}`",
+            path.display().cyan()
+        )?;
+        return Ok(());
+    }
+    writeln!(
+        printer.stderr(),
+        "Would update `{}`:",
+        path.display().cyan()
+    )?;
+    // Nothing is resolved in a dry run, so only the lock's versions of removed packages are known
+    print_dep_diff(
+        printer,
+        &before_dependencies,
+        &after_dependencies,
+        lock,
+        None,
+    )?;
+    if before_requires_python != after_requires_python {
+        writeln!(
+            printer.stderr(),
+            "  {} `requires-python`: `{}` -> `{}`",
+            "~".yellow().bold(),
+            before_requires_python.as_deref().unwrap_or("none").cyan(),
+            after_requires_python.as_deref().unwrap_or("none").cyan()
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the dependencies removed (`-`) and added (`+`) between two lists of requirements
+///
+/// Each package is followed by its version in the corresponding `uv.lock`, when there is one.
+fn print_dep_diff(
+    printer: &Printer,
+    before: &[String],
+    after: &[String],
+    before_lock: Option<&str>,
+    after_lock: Option<&str>,
+) -> Result<()> {
+    let before_versions = before_lock.map(locked_versions).unwrap_or_default();
+    let after_versions = after_lock.map(locked_versions).unwrap_or_default();
+    let resolved = |dependency: &str, versions: &HashMap<String, String>| {
+        requirement_name(dependency)
+            .and_then(|name| versions.get(&name))
+            .map(|version| format!(" ({})", version.dimmed()))
+            .unwrap_or_default()
+    };
+    for dependency in before
+        .iter()
+        .filter(|dependency| !after.contains(dependency))
+    {
+        writeln!(
+            printer.stderr(),
+            "  {} {}{}",
+            "-".red().bold(),
+            dependency.red(),
+            resolved(dependency, &before_versions)
+        )?;
+    }
+    for dependency in after
+        .iter()
+        .filter(|dependency| !before.contains(dependency))
+    {
+        writeln!(
+            printer.stderr(),
+            "  {} {}{}",
+            "+".green().bold(),
+            dependency.green(),
+            resolved(dependency, &after_versions)
+        )?;
+    }
     Ok(())
 }
 
-pub fn edit(printer: &Printer, file: &Path, editor: Option<&str>) -> Result<()> {
+/// Provides the version of each package in a `uv.lock`, keyed by normalized name
+fn locked_versions(lock: &str) -> HashMap<String, String> {
+    let Ok(table) = lock.parse::<toml::Table>() else {
+        return HashMap::new();
+    };
+    let Some(packages) = table.get("package").and_then(|p| p.as_array()) else {
+        return HashMap::new();
+    };
+    packages
+        .iter()
+        .filter_map(|package| {
+            let name = requirement_name(package.get("name")?.as_str()?)?;
+            let version = package.get("version")?.as_str()?;
+            Some((name, version.to_string()))
+        })
+        .collect()
+}
+
+/// The notebook metadata key holding juv's settings for the notebook
+const JUV_METADATA_KEY: &str = "juv";
+
+/// The runtime saved with `juv run --remember-runtime`, if any
+fn remembered_runtime(nb: &Notebook) -> Option<String> {
+    let settings = nb.as_ref().metadata.additional.get(JUV_METADATA_KEY)?;
+    settings.get("runtime")?.as_str().map(str::to_string)
+}
+
+/// Saves the runtime for later runs of the notebook, keeping juv's other settings
+fn remember(nb: &mut Notebook, runtime: &str) {
+    let settings = nb
+        .as_mut()
+        .metadata
+        .additional
+        .entry(JUV_METADATA_KEY.to_string())
+        .or_insert_with(|| serde_json::json!({}));
+    if !settings.is_object() {
+        *settings = serde_json::json!({});
+    }
+    settings["runtime"] = runtime.into();
+}
+
+/// The notebook metadata key holding the contents of the notebook's `uv.lock`
+const LOCK_METADATA_KEY: &str = "uv.lock";
+
+pub fn edit(
+    printer: &Printer,
+    file: &Path,
+    editor: Option<&str>,
+    wait: Option<bool>,
+    cell: Option<usize>,
+) -> Result<()> {
+    ensure_writable(file)?;
+    if let Some(index) = cell {
+        return edit_cell(printer, file, editor, wait, index);
+    }
     let nb = Notebook::from_path(file)?;
     let mut temp_file = tempfile::Builder::new().suffix(".md").tempfile()?;
     {
         let mut buffer = BufWriter::new(&mut temp_file);
-        write_markdown(&mut buffer, nb.as_ref())?;
+        write_markdown(&mut buffer, &nb, RenderOptions::default())?;
         buffer.flush()?;
     }
 
-    let status = match editor {
-        Some(editor) => Command::new(editor).arg(temp_file.path()).status()?,
+    open_in_editor(printer, editor, wait, temp_file.path())?;
+
+    let update = std::fs::read_to_string(temp_file.path())?;
+
+    println!("{}", update);
+
+    // TODO: Need to parse the markdown "cell" contents and update the corresponding cells
+
+    Ok(())
+}
+
+/// Edits the source of a single cell, splicing the result back into the notebook
+fn edit_cell(
+    printer: &Printer,
+    file: &Path,
+    editor: Option<&str>,
+    wait: Option<bool>,
+    index: usize,
+) -> Result<()> {
+    let nb = Notebook::from_path(file)?;
+    let cells = nb.cells();
+    let Some(cell) = cells.get(index) else {
+        bail!(
+            "Cell index {} is out of range, `{}` has {} cells",
+            index,
+            file.display(),
+            cells.len()
+        );
+    };
+    let (id, source, suffix) = match cell {
+        nbformat::v4::Cell::Code { id, source, .. } => (id, source, ".py"),
+        nbformat::v4::Cell::Markdown { id, source, .. } => (id, source, ".md"),
+        nbformat::v4::Cell::Raw { id, source, .. } => (id, source, ".txt"),
+    };
+    let original = source.join("");
+
+    let temp_file = tempfile::Builder::new().suffix(suffix).tempfile()?;
+    std::fs::write(temp_file.path(), &original)?;
+    open_in_editor(printer, editor, wait, temp_file.path())?;
+
+    let update = std::fs::read_to_string(temp_file.path())?;
+    // Editors usually add a final newline, but the last line of a cell doesn't have one
+    let update = update.strip_suffix('\n').unwrap_or(&update);
+    if update == original {
+        writeln!(printer.stderr(), "No changes to cell {}", index)?;
+        return Ok(());
+    }
+
+    // The notebook may have changed while the editor was open, so find the cell by id. Cells
+    // without a stored id get a new one on every read, so those are found by position, as
+    // long as no cells were added or removed.
+    let id = id.clone();
+    let count = cells.len();
+    let _lock = NotebookLock::acquire(file)?;
+    let stored_ids = Notebook::has_stored_cell_ids(file)?;
+    let mut nb = Notebook::from_path(file)?;
+    if !stored_ids && nb.cells().len() != count {
+        bail!(
+            "Cells were added to or removed from `{}` while editing, so cell {} can't be found",
+            file.display(),
+            index
+        );
+    }
+    let cell = if stored_ids {
+        nb.cells_mut()
+            .iter_mut()
+            .map(cell_id_and_source)
+            .find(|(cell_id, _)| **cell_id == id)
+    } else {
+        nb.cells_mut().get_mut(index).map(cell_id_and_source)
+    };
+    let Some((_, source)) = cell else {
+        bail!(
+            "Cell {} was removed from `{}` while editing",
+            index,
+            file.display()
+        );
+    };
+    *source = split_source(update);
+    nb.write(file)?;
+
+    writeln!(
+        printer.stderr(),
+        "Updated cell {} in `{}`",
+        index,
+        file.display().cyan()
+    )?;
+    Ok(())
+}
+
+/// Provides the id and (mutable) source of any kind of cell
+fn cell_id_and_source(cell: &mut nbformat::v4::Cell) -> (&nbformat::v4::CellId, &mut Vec<String>) {
+    match cell {
+        nbformat::v4::Cell::Code { id, source, .. }
+        | nbformat::v4::Cell::Markdown { id, source, .. }
+        | nbformat::v4::Cell::Raw { id, source, .. } => (id, source),
+    }
+}
+
+/// Opens a file in the user's editor, exiting if the editor is missing or fails
+///
+/// The editor may include arguments (e.g., `code -n`). GUI editors return right away unless
+/// told to wait, so their wait flag is added for the ones juv knows, or when `wait` is set.
+fn open_in_editor(
+    printer: &Printer,
+    editor: Option<&str>,
+    wait: Option<bool>,
+    path: &Path,
+) -> Result<()> {
+    let mut words = editor.into_iter().flat_map(str::split_whitespace);
+    let status = match words.next() {
+        Some(program) => {
+            let mut args: Vec<&str> = words.collect();
+            let wait_flag = editor_wait_flag(program);
+            if wait.unwrap_or(wait_flag.is_some())
+                && !args.iter().any(|arg| ["--wait", "-w"].contains(arg))
+            {
+                args.push(wait_flag.unwrap_or("--wait"));
+            }
+            Command::new(program).args(args).arg(path).status()?
+        }
         None => {
             writeln!(
                 printer.stderr(),
-                "{}: No editor specified. Please set the EDITOR environment variable or use the `{}` flag.",
+                "{}: No editor specified. Please set the VISUAL or EDITOR environment variable or use the `{}` flag.",
                 "error".red().bold(),
                 "--editor".yellow().bold()
             )?;
@@ -277,69 +1803,229 @@ pub fn edit(printer: &Printer, file: &Path, editor: Option<&str>) -> Result<()>
         std::process::exit(1);
     }
 
-    let update = std::fs::read_to_string(temp_file.path())?;
+    Ok(())
+}
 
-    println!("{}", update);
+/// Provides the flag that makes a GUI editor block until the file is closed, if it needs one
+fn editor_wait_flag(program: &str) -> Option<&'static str> {
+    let name = Path::new(program).file_stem()?.to_str()?;
+    match name {
+        "code" | "code-insiders" | "codium" | "cursor" | "subl" | "zed" | "atom" => Some("--wait"),
+        "mate" => Some("-w"),
+        _ => None,
+    }
+}
 
-    // TODO: Need to parse the markdown "cell" contents and update the corresponding cells
+pub fn meta_show(path: &Path, json: bool) -> Result<()> {
+    let nb = Notebook::from_path(path)?;
+    let Some(meta) = find_inline_metadata(&nb) else {
+        bail!(
+            "`{}` has no inline script metadata. Use `juv add` to add dependencies.",
+            path.display()
+        );
+    };
+    let toml = metadata_toml(&meta);
+    let mut stdout = io::stdout().lock();
+    if json {
+        let value: toml::Value = match toml::from_str(&toml) {
+            Ok(value) => value,
+            Err(err) => bail!(
+                "The inline script metadata of `{}` is invalid: {}",
+                path.display(),
+                err
+            ),
+        };
+        writeln!(stdout, "{}", serde_json::to_string_pretty(&value)?)?;
+    } else {
+        stdout.write_all(toml.as_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn meta_edit(
+    printer: &Printer,
+    path: &Path,
+    editor: Option<&str>,
+    wait: Option<bool>,
+) -> Result<()> {
+    ensure_writable(path)?;
+    let nb = Notebook::from_path(path)?;
+    let Some(meta) = find_inline_metadata(&nb) else {
+        bail!(
+            "`{}` has no inline script metadata. Use `juv add` to add dependencies.",
+            path.display()
+        );
+    };
+    let original = metadata_toml(&meta);
+
+    let temp_file = tempfile::Builder::new().suffix(".toml").tempfile()?;
+    std::fs::write(temp_file.path(), &original)?;
+    open_in_editor(printer, editor, wait, temp_file.path())?;
+
+    let update = std::fs::read_to_string(temp_file.path())?;
+    if update == original {
+        writeln!(printer.stderr(), "No changes to the inline script metadata")?;
+        return Ok(());
+    }
+    if let Err(err) = toml::from_str::<toml::Value>(&update) {
+        writeln!(
+            printer.stderr(),
+            "{}: The edited metadata is not valid TOML, so `{}` was left unchanged\n\n{}",
+            "error".red().bold(),
+            path.display().cyan(),
+            err.to_string().trim_end()
+        )?;
+        std::process::exit(1);
+    }
+    let block = metadata_block(&update);
+
+    // The notebook may have changed while the editor was open, so find the block again
+    let _lock = NotebookLock::acquire(path)?;
+    let mut nb = Notebook::from_path(path)?;
+    let Some(source) = nb.cells_mut().iter_mut().find_map(|cell| match cell {
+        nbformat::v4::Cell::Code { source, .. } if PEP723_REGEX.is_match(&join_source(source)) => {
+            Some(source)
+        }
+        _ => None,
+    }) else {
+        bail!(
+            "The inline script metadata was removed from `{}` while editing",
+            path.display()
+        );
+    };
+    let contents = join_source(source);
+    *source = split_source(&PEP723_REGEX.replace(&contents, regex::NoExpand(&block)));
+    nb.write(path)?;
 
+    writeln!(
+        printer.stderr(),
+        "Updated the inline script metadata of `{}`",
+        path.display().cyan()
+    )?;
     Ok(())
 }
 
-pub fn clear(printer: &Printer, targets: &[String], check: bool) -> Result<()> {
-    let mut paths: Vec<PathBuf> = Vec::new();
+pub fn fmt(
+    printer: &Printer,
+    targets: &[String],
+    check: bool,
+    format: CheckFormat,
+    nbformat_minor: Option<i32>,
+) -> Result<()> {
+    let paths = collect_notebooks(printer, targets)?;
 
-    // Collect notebook paths from the specified targets
-    for target in targets {
-        let path = Path::new(target);
-        if path.is_dir() {
-            // Use glob to find .ipynb files in directory
-            glob::glob(&format!("{}/*.ipynb", path.display()))?.for_each(|entry| {
-                if let Ok(notebook_path) = entry {
-                    paths.push(notebook_path);
-                }
-            });
-        } else if path.is_file() && path.extension().map_or(false, |ext| ext == "ipynb") {
-            paths.push(path.to_path_buf());
+    let mut unformatted = vec![];
+    for path in &paths {
+        let _lock = if check {
+            None
         } else {
-            writeln!(
+            ensure_writable(path)?;
+            Some(NotebookLock::acquire(path)?)
+        };
+        let (notebook, changed) = format_notebook(path, nbformat_minor)?;
+        if !changed {
+            continue;
+        }
+        if !check {
+            notebook.write(path)?;
+            writeln!(printer.stderr(), "Formatted `{}`", path.display().cyan())?;
+        }
+        unformatted.push(path.as_path());
+    }
+
+    if check {
+        return report_check(printer, format, &unformatted, "formatted", "juv fmt");
+    }
+    let unchanged = paths.len() - unformatted.len();
+    if unchanged > 0 {
+        writeln!(
+            printer.stderr(),
+            "{} {} already formatted",
+            unchanged.to_string().cyan().bold(),
+            if unchanged == 1 {
+                "notebook"
+            } else {
+                "notebooks"
+            }
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads a notebook in its canonical form, along with whether that differs from the file
+fn format_notebook(path: &Path, nbformat_minor: Option<i32>) -> Result<(Notebook, bool)> {
+    let original = Notebook::read_json(path)?;
+    let mut notebook = Notebook::from_path(path)?;
+    notebook.normalize();
+    if let Some(minor) = nbformat_minor {
+        ensure_nbformat_minor(path, &notebook, minor)?;
+        notebook.set_nbformat_minor(minor);
+    }
+    let changed = notebook.to_formatted_json()? != original;
+    Ok((notebook, changed))
+}
+
+pub fn clear(
+    printer: &Printer,
+    targets: &[String],
+    check: bool,
+    format: CheckFormat,
+    policy: ClearPolicy,
+    since: Option<&str>,
+    nbformat_minor: Option<i32>,
+) -> Result<()> {
+    let mut paths = collect_notebooks(printer, targets)?;
+
+    if let Some(rev) = since {
+        match changed_since(rev)? {
+            Some(changed) => paths.retain(|path| {
+                std::fs::canonicalize(path).is_ok_and(|path| changed.contains(&path))
+            }),
+            None => writeln!(
                 printer.stderr(),
-                "{}: Skipping `{}` because it is not a notebook",
+                "{}: Not in a git repository, so `{}` is ignored",
                 "warning".yellow().bold(),
-                path.display().cyan(),
-            )?;
+                "--since".yellow().bold()
+            )?,
         }
     }
 
     if check {
-        let mut any_not_cleared = false;
+        let mut dirty = vec![];
 
         // Check each notebook to see if it is already cleared
         for path in &paths {
             let notebook = Notebook::from_path(path)?;
-            if !notebook.is_cleared() {
-                writeln!(printer.stderr(), "{}", path.display().magenta())?;
-                any_not_cleared = true;
+            let outdated = match nbformat_minor {
+                Some(minor) => {
+                    ensure_nbformat_minor(path, &notebook, minor)?;
+                    minor != Notebook::read_nbformat_minor(path)?
+                }
+                None => false,
+            };
+            if !notebook.is_cleared(policy) || outdated {
+                dirty.push(path.as_path());
             }
         }
 
-        if any_not_cleared {
-            writeln!(
-                printer.stderr(),
-                "{}: Some notebooks are not cleared. Use {} to fix.",
-                "error".red(),
-                "juv clear".yellow().bold(),
-            )?;
-            std::process::exit(1);
-        } else {
-            writeln!(printer.stderr(), "All notebooks are cleared")?;
-        }
+        report_check(printer, format, &dirty, "cleared", "juv clear")?;
     } else {
         // Clear the outputs in each notebook
         for path in &paths {
-            let mut notebook = Notebook::from_path(path)?;
-            notebook.clear_cells()?;
-            std::fs::write(path, serde_json::to_string_pretty(notebook.as_ref())?)?;
+            ensure_writable(path)?;
+            let _lock = NotebookLock::acquire(path)?;
+            let mut notebook = if policy.outputs == ClearOutputs::All {
+                // Avoid holding the outputs we are about to drop in memory
+                Notebook::from_path_without_outputs(path)?
+            } else {
+                Notebook::from_path(path)?
+            };
+            notebook.clear_cells(policy)?;
+            if let Some(minor) = nbformat_minor {
+                ensure_nbformat_minor(path, &notebook, minor)?;
+                notebook.set_nbformat_minor(minor);
+            }
+            notebook.write(path)?;
             writeln!(
                 printer.stderr(),
                 "Cleared output from `{}`",
@@ -358,112 +2044,745 @@ pub fn clear(printer: &Printer, targets: &[String], check: bool) -> Result<()> {
     Ok(())
 }
 
-pub fn cat(
-    _printer: &Printer,
-    file: &std::path::Path,
-    script: bool,
-    pager: Option<&str>,
+/// Checks that a requested `nbformat_minor` can still represent the notebook
+fn ensure_nbformat_minor(path: &Path, notebook: &Notebook, minor: i32) -> Result<()> {
+    let min = notebook.min_nbformat_minor();
+    if minor < min {
+        bail!(
+            "`{}` needs nbformat 4.{} for its cell ids, so it can't be set to 4.{}",
+            path.display(),
+            min,
+            minor
+        );
+    }
+    Ok(())
+}
+
+/// How the results of `--check` are reported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckFormat {
+    /// Only list the failing notebooks on stderr
+    Text,
+    /// Also print an object with a `clean` boolean and the `dirty` notebook paths to stdout
+    Json,
+    /// Also print a GitHub Actions error annotation to stdout for each failing notebook
+    Github,
+}
+
+/// Reports the notebooks that failed a `--check`, exiting with an error if there are any
+///
+/// `state` describes what a passing notebook is (e.g., "cleared") and `fix` is the command
+/// that fixes a failing one.
+fn report_check(
+    printer: &Printer,
+    format: CheckFormat,
+    failed: &[&Path],
+    state: &str,
+    fix: &str,
 ) -> Result<()> {
-    let nb = Notebook::from_path(file)?;
-    let mut writer: Box<dyn Write> = match pager.map(str::trim) {
-        Some("") | None => Box::new(BufWriter::new(io::stdout().lock())),
-        Some(pager) => {
-            let mut command = Command::new(pager);
-            if pager == "bat" {
-                let ext = if script { "py" } else { "md" };
-                // special case `bat` to add additional flags
-                command
-                    .arg("--language")
-                    .arg(ext)
-                    .arg("--file-name")
-                    .arg(format!(
-                        "{}.{}",
-                        file.file_stem()
-                            .unwrap_or("stdin".as_ref())
-                            .to_string_lossy(),
-                        ext
-                    ));
+    for path in failed {
+        writeln!(printer.stderr(), "{}", path.display().magenta())?;
+    }
+    match format {
+        CheckFormat::Text => {}
+        CheckFormat::Json => {
+            let dirty: Vec<_> = failed.iter().map(|path| path.to_string_lossy()).collect();
+            let json = serde_json::json!({ "clean": dirty.is_empty(), "dirty": dirty });
+            writeln!(io::stdout(), "{}", serde_json::to_string(&json)?)?;
+        }
+        CheckFormat::Github => {
+            for path in failed {
+                writeln!(
+                    io::stdout(),
+                    "::error file={},title={}::{}",
+                    escape_github_property(&path.to_string_lossy()),
+                    escape_github_property(&format!("Notebook is not {state}")),
+                    escape_github_data(&format!("Run `{} {}` to fix.", fix, path.display()))
+                )?;
             }
-            let child = command.stdin(Stdio::piped()).spawn()?;
-            // Ok to unwrap because we know we set stdin to piped
-            Box::new(BufWriter::new(child.stdin.unwrap()))
         }
-    };
-
-    if script {
-        write_script(&mut writer, nb.as_ref())?;
-    } else {
-        write_markdown(&mut writer, nb.as_ref())?;
-    };
+    }
 
-    writer.flush()?;
+    if failed.is_empty() {
+        writeln!(printer.stderr(), "All notebooks are {}", state)?;
+        return Ok(());
+    }
+    writeln!(
+        printer.stderr(),
+        "{}: Some notebooks are not {}. Use {} to fix.",
+        "error".red(),
+        state,
+        fix.yellow().bold(),
+    )?;
+    std::process::exit(1);
+}
+
+/// Escapes the message of a GitHub Actions workflow command
+fn escape_github_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a property (e.g., `file`) of a GitHub Actions workflow command
+fn escape_github_property(property: &str) -> String {
+    escape_github_data(property)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+/// Finds the files that changed since a git revision, including untracked files
+///
+/// Returns `None` outside of a git repository.
+fn changed_since(rev: &str) -> Result<Option<std::collections::HashSet<PathBuf>>> {
+    let root = match Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .stderr(Stdio::null())
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            PathBuf::from(String::from_utf8_lossy(&output.stdout).trim())
+        }
+        _ => return Ok(None),
+    };
+    let mut changed = std::collections::HashSet::new();
+    for args in [
+        &["diff", "--name-only", "-z", rev, "--"][..],
+        &["ls-files", "--others", "--exclude-standard", "-z"][..],
+    ] {
+        // Both list paths relative to the root of the repository when run from it
+        let output = Command::new("git").args(args).current_dir(&root).output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("git command failed: {}", stderr.trim());
+        }
+        changed.extend(
+            String::from_utf8_lossy(&output.stdout)
+                .split('\0')
+                .filter(|path| !path.is_empty())
+                // Deleted files can't be canonicalized, but there's nothing to clear in them
+                .filter_map(|path| std::fs::canonicalize(root.join(path)).ok()),
+        );
+    }
+    Ok(Some(changed))
+}
+
+/// Collects the notebooks among `targets`, searching directories (but not subdirectories)
+/// and warning about anything that isn't a notebook
+fn collect_notebooks(printer: &Printer, targets: &[String]) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for target in targets {
+        let path = Path::new(target);
+        if path.is_dir() {
+            // Use glob to find .ipynb (and .ipynb.gz) files in directory
+            for pattern in ["*.ipynb", "*.ipynb.gz"] {
+                glob::glob(&format!("{}/{}", path.display(), pattern))?.for_each(|entry| {
+                    if let Ok(notebook_path) = entry {
+                        paths.push(notebook_path);
+                    }
+                });
+            }
+        } else if path.is_file() && is_notebook_path(path) {
+            paths.push(path.to_path_buf());
+        } else {
+            writeln!(
+                printer.stderr(),
+                "{}: Skipping `{}` because it is not a notebook",
+                "warning".yellow().bold(),
+                path.display().cyan(),
+            )?;
+        }
+    }
+    Ok(paths)
+}
+
+pub fn cat(
+    _printer: &Printer,
+    files: &[PathBuf],
+    script: bool,
+    pager: Option<&str>,
+    output: Option<&Path>,
+    plain: bool,
+    render_options: RenderOptions,
+) -> Result<()> {
+    let mut contents = vec![];
+    for (i, file) in files.iter().enumerate() {
+        if files.len() > 1 {
+            if i > 0 {
+                contents.write_all(b"\n\n")?;
+            }
+            // A comment in either format, since `#` would be a heading in markdown
+            if script {
+                writeln!(contents, "# ===== {} =====\n", file.display())?;
+            } else {
+                writeln!(contents, "<!-- ===== {} ===== -->\n", file.display())?;
+            }
+        }
+        let nb = if render_options.outputs {
+            Notebook::from_path(file)?
+        } else {
+            Notebook::from_path_without_outputs(file)?
+        };
+        if script {
+            write_script(&mut contents, nb.as_ref(), render_options)?;
+        } else {
+            write_markdown(&mut contents, &nb, render_options)?;
+        };
+    }
+
+    // Colors are only kept where they'll be shown (or with `--color=always`)
+    fn color_choice(stream: &impl anstream::stream::RawStream) -> bool {
+        anstream::AutoStream::choice(stream) != anstream::ColorChoice::Never
+    }
+    let (mut writer, colored): (Box<dyn Write>, bool) = match (output, pager.map(str::trim)) {
+        (Some(output), _) => {
+            if let Some(parent) = output.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = std::fs::File::create(output)?;
+            let colored = color_choice(&file);
+            (Box::new(BufWriter::new(file)), colored)
+        }
+        (None, Some("") | None) => (
+            Box::new(BufWriter::new(io::stdout().lock())),
+            color_choice(&io::stdout()),
+        ),
+        (None, Some(pager)) => {
+            let mut command = Command::new(pager);
+            if pager == "bat" {
+                let ext = if script { "py" } else { "md" };
+                // special case `bat` to add additional flags
+                command
+                    .arg("--language")
+                    .arg(ext)
+                    .arg("--file-name")
+                    .arg(format!(
+                        "{}.{}",
+                        match files {
+                            [file] => file.file_stem().unwrap_or("stdin".as_ref()),
+                            _ => "stdin".as_ref(),
+                        }
+                        .to_string_lossy(),
+                        ext
+                    ));
+            }
+            let child = command.stdin(Stdio::piped()).spawn()?;
+            // Ok to unwrap because we know we set stdin to piped
+            let stdin = child.stdin.unwrap();
+            // The pager shows the contents in the terminal
+            (Box::new(BufWriter::new(stdin)), color_choice(&io::stdout()))
+        }
+    };
+
+    if plain || !colored {
+        writer.write_all(strip_ansi(&String::from_utf8_lossy(&contents)).as_bytes())?;
+    } else {
+        writer.write_all(&contents)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Removes ANSI escape sequences (colors, cursor movement, hyperlinks, ...) from text
+fn strip_ansi(text: &str) -> std::borrow::Cow<'_, str> {
+    ANSI_REGEX.replace_all(text, "")
+}
+
+static ANSI_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(\x07|\x1b\\)|\x1b[@-Z\\-_]").unwrap()
+});
+
+/// Options for rendering a notebook as a script or markdown
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOptions {
+    /// The number of blank lines between cells
+    pub blank_lines: usize,
+    /// Whether to leave out cells without any source
+    pub skip_empty: bool,
+    /// Whether to include the text of code cell outputs
+    pub outputs: bool,
+    /// The most lines of output to show per cell, if limited
+    pub max_output_lines: Option<usize>,
+    /// Whether to annotate code cells with their recorded execution count
+    pub execution_counts: bool,
+    /// The column to wrap long source lines at, if any
+    pub wrap: Option<usize>,
+    /// The kinds of cells to render
+    pub kinds: CellKinds,
+}
+
+/// A set of cell kinds
+#[derive(Debug, Clone, Copy)]
+pub struct CellKinds {
+    pub code: bool,
+    pub markdown: bool,
+    pub raw: bool,
+}
+
+impl CellKinds {
+    /// Every kind of cell
+    pub const ALL: Self = Self {
+        code: true,
+        markdown: true,
+        raw: true,
+    };
+
+    /// Whether the cell is of one of the kinds
+    fn contains(&self, cell: &nbformat::v4::Cell) -> bool {
+        match cell {
+            nbformat::v4::Cell::Code { .. } => self.code,
+            nbformat::v4::Cell::Markdown { .. } => self.markdown,
+            nbformat::v4::Cell::Raw { .. } => self.raw,
+        }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            blank_lines: 1,
+            skip_empty: false,
+            outputs: false,
+            max_output_lines: None,
+            execution_counts: false,
+            wrap: None,
+            kinds: CellKinds::ALL,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Writes the separator that goes between two cells
+    fn write_separator(&self, writer: &mut impl Write) -> io::Result<()> {
+        // The previous cell doesn't end with a newline, so end its last line first
+        writer.write_all("\n".repeat(self.blank_lines + 1).as_bytes())
+    }
+
+    /// The execution count to annotate a code cell with, if any
+    fn execution_count(&self, execution_count: &Option<i32>) -> Option<i32> {
+        execution_count.filter(|_| self.execution_counts)
+    }
 
+    /// The blocks to show for a code cell's outputs, with each error in a block of its own
+    fn output_blocks(&self, outputs: &[nbformat::v4::Output]) -> Vec<OutputBlock> {
+        if !self.outputs {
+            return vec![];
+        }
+        let mut blocks: Vec<OutputBlock> = vec![];
+        for output in outputs {
+            let (error, lines) = match output_error(output) {
+                Some(lines) => (true, lines),
+                None => match output_text(output) {
+                    Some(text) => (false, text.lines().map(str::to_string).collect()),
+                    None => continue,
+                },
+            };
+            match blocks.last_mut() {
+                Some(block) if !error && !block.error => block.lines.extend(lines),
+                _ => blocks.push(OutputBlock { error, lines }),
+            }
+        }
+        blocks.retain(|block| !block.lines.is_empty());
+
+        if let Some(max) = self.max_output_lines {
+            let total: usize = blocks.iter().map(|block| block.lines.len()).sum();
+            if total > max {
+                let mut budget = max;
+                for block in &mut blocks {
+                    let keep = block.lines.len().min(budget);
+                    block.lines.truncate(keep);
+                    budget -= keep;
+                }
+                blocks.retain(|block| !block.lines.is_empty());
+                let marker = format!("... (truncated, {} more lines)", total - max);
+                match blocks.last_mut() {
+                    Some(block) => block.lines.push(marker),
+                    None => blocks.push(OutputBlock {
+                        error: false,
+                        lines: vec![marker],
+                    }),
+                }
+            }
+        }
+        blocks
+    }
+
+    /// The cells to render, in order
+    fn cells<'a>(
+        &self,
+        nb: &'a nbformat::v4::Notebook,
+    ) -> impl Iterator<Item = &'a nbformat::v4::Cell> {
+        let skip_empty = self.skip_empty;
+        let kinds = self.kinds;
+        nb.cells
+            .iter()
+            .filter(move |cell| kinds.contains(cell))
+            .filter(move |cell| !(skip_empty && is_empty_cell(cell)))
+    }
+}
+
+/// A run of a code cell's outputs that is rendered together
+struct OutputBlock {
+    /// Whether the block is an error (rather than regular output)
+    error: bool,
+    lines: Vec<String>,
+}
+
+/// Provides the lines to show for an error output: its name and value (in red), followed by
+/// its traceback
+fn output_error(output: &nbformat::v4::Output) -> Option<Vec<String>> {
+    let value = serde_json::to_value(output).ok()?;
+    if value.get("output_type")?.as_str()? != "error" {
+        return None;
+    }
+    let ename = value
+        .get("ename")
+        .and_then(|v| v.as_str())
+        .unwrap_or("Error");
+    let evalue = value.get("evalue").and_then(|v| v.as_str()).unwrap_or("");
+    // Like the traceback's own colors, these are stripped unless they'll be shown
+    let mut lines = vec![format!("{ename}: {evalue}").red().bold().to_string()];
+    lines.extend(
+        value
+            .get("traceback")?
+            .as_array()?
+            .iter()
+            .filter_map(|line| line.as_str())
+            .flat_map(str::lines)
+            .map(str::to_string),
+    );
+    Some(lines)
+}
+
+/// Provides the text of an output: a stream's text or the `text/plain` of a result
+fn output_text(output: &nbformat::v4::Output) -> Option<String> {
+    fn text(value: &serde_json::Value) -> Option<String> {
+        match value {
+            serde_json::Value::String(text) => Some(text.clone()),
+            serde_json::Value::Array(lines) => lines.iter().map(|line| line.as_str()).collect(),
+            _ => None,
+        }
+    }
+    let value = serde_json::to_value(output).ok()?;
+    let mut text = match value.get("output_type")?.as_str()? {
+        "stream" => text(value.get("text")?)?,
+        "execute_result" | "display_data" => text(value.get("data")?.get("text/plain")?)?,
+        _ => return None,
+    };
+    // Streams often end with a newline, results never do
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    Some(text)
+}
+
+/// Whether a code cell has outputs that run JavaScript when a frontend renders them
+///
+/// Plain HTML (e.g., a pandas table) is common and harmless, so only scripts count.
+fn has_active_outputs(cell: &nbformat::v4::Cell) -> bool {
+    let nbformat::v4::Cell::Code { outputs, .. } = cell else {
+        return false;
+    };
+    outputs.iter().any(|output| {
+        let Ok(value) = serde_json::to_value(output) else {
+            return false;
+        };
+        let Some(data) = value.get("data") else {
+            return false;
+        };
+        data.get("application/javascript").is_some()
+            || ["text/html", "image/svg+xml"].iter().any(|mime| {
+                data.get(mime).is_some_and(|markup| {
+                    let markup = markup.to_string().to_lowercase();
+                    markup.contains("<script") || markup.contains("javascript:")
+                })
+            })
+    })
+}
+
+/// Whether a cell's source is empty or only whitespace, a common leftover of interactive editing
+fn is_empty_cell(cell: &nbformat::v4::Cell) -> bool {
+    let (nbformat::v4::Cell::Code { source, .. }
+    | nbformat::v4::Cell::Markdown { source, .. }
+    | nbformat::v4::Cell::Raw { source, .. }) = cell;
+    source.iter().all(|line| line.trim().is_empty())
+}
+
+/// Writes a cell's source, starting on a new line and prefixing each line with `prefix`.
+///
+/// Nothing is written for an empty cell, so its header (or fence) stands on its own.
+fn write_source(
+    writer: &mut impl Write,
+    source: &[String],
+    prefix: &str,
+    wrap: Option<usize>,
+) -> io::Result<()> {
+    if source.iter().all(String::is_empty) {
+        return Ok(());
+    }
+    writer.write_all(b"\n")?;
+    for line in source.iter().flat_map(|s| s.split_inclusive('\n')) {
+        write_line(writer, line, prefix, wrap)?;
+    }
     Ok(())
 }
 
-fn write_script(writer: &mut impl Write, nb: &nbformat::v4::Notebook) -> Result<()> {
-    for (i, cell) in nb.cells.iter().enumerate() {
+/// Writes a line (with its newline, if any) after `prefix`, wrapping it at `wrap` columns
+///
+/// Each wrapped piece gets the prefix, so a wrapped comment stays a comment, but wrapped code
+/// is only meant for reading.
+fn write_line(
+    writer: &mut impl Write,
+    line: &str,
+    prefix: &str,
+    wrap: Option<usize>,
+) -> io::Result<()> {
+    let (content, newline) = match line.strip_suffix('\n') {
+        Some(content) => (content, "\n"),
+        None => (line, ""),
+    };
+    let pieces = match wrap {
+        Some(width) => wrap_line(content, width.saturating_sub(prefix.chars().count()).max(1)),
+        None => vec![content],
+    };
+    for (i, piece) in pieces.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b"\n")?;
+        }
+        writer.write_all(prefix.as_bytes())?;
+        writer.write_all(piece.as_bytes())?;
+    }
+    writer.write_all(newline.as_bytes())
+}
+
+/// Splits a line into pieces of at most `width` characters, breaking at a space (which is
+/// dropped) where possible
+fn wrap_line(line: &str, width: usize) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut rest = line;
+    // A space right at the limit is as good a break as one before it
+    while let Some((limit, next)) = rest.char_indices().nth(width) {
+        let space = if matches!(next, ' ' | '\t') {
+            Some(limit)
+        } else {
+            rest[..limit].rfind([' ', '\t'])
+        };
+        match space {
+            Some(space) if space > 0 => {
+                pieces.push(&rest[..space]);
+                rest = &rest[space + 1..];
+            }
+            _ => {
+                pieces.push(&rest[..limit]);
+                rest = &rest[limit..];
+            }
+        }
+    }
+    pieces.push(rest);
+    pieces
+}
+
+fn write_script(
+    writer: &mut impl Write,
+    nb: &nbformat::v4::Notebook,
+    options: RenderOptions,
+) -> Result<()> {
+    let mut empty = true;
+    for (i, cell) in options.cells(nb).enumerate() {
         if i > 0 {
-            // Add a newline between cells
-            writer.write_all(b"\n\n")?;
+            options.write_separator(writer)?;
         }
+        empty = false;
         match cell {
-            nbformat::v4::Cell::Code { source, .. } => {
-                writer.write_all(b"# %%\n")?;
-                for line in source.iter() {
-                    writer.write_all(line.as_bytes())?;
+            nbformat::v4::Cell::Code {
+                source,
+                outputs,
+                execution_count,
+                ..
+            } => {
+                writer.write_all(b"# %%")?;
+                if let Some(count) = options.execution_count(execution_count) {
+                    write!(writer, "\n# [{count}]:")?;
+                }
+                write_source(writer, source, "", options.wrap)?;
+                for block in options.output_blocks(outputs) {
+                    let label = if block.error { "Error" } else { "Output" };
+                    write!(writer, "\n# {label}:")?;
+                    for line in block.lines {
+                        write!(writer, "\n# {line}")?;
+                    }
                 }
             }
             nbformat::v4::Cell::Markdown { source, .. } => {
-                writer.write_all(b"# %% [markdown]\n")?;
-                for line in source.iter() {
-                    writer.write_all(b"# ")?;
-                    writer.write_all(line.as_bytes())?;
-                }
+                writer.write_all(b"# %% [markdown]")?;
+                write_source(writer, source, "# ", options.wrap)?;
             }
             nbformat::v4::Cell::Raw { source, .. } => {
-                writer.write_all(b"# %% [raw]\n")?;
-                for line in source.iter() {
-                    writer.write_all(b"# ")?;
-                    writer.write_all(line.as_bytes())?;
-                }
+                writer.write_all(b"# %% [raw]")?;
+                write_source(writer, source, "# ", options.wrap)?;
             }
         }
     }
+    if !empty {
+        // End with a newline, like any POSIX text file
+        writer.write_all(b"\n")?;
+    }
     Ok(())
 }
 
-fn write_markdown(writer: &mut impl Write, nb: &nbformat::v4::Notebook) -> Result<()> {
-    for (i, cell) in nb.cells.iter().enumerate() {
-        if i > 0 {
-            // Add a newline between cells
-            writer.write_all(b"\n\n")?;
+fn write_markdown(writer: &mut impl Write, nb: &Notebook, options: RenderOptions) -> Result<()> {
+    // Markdown has no block for an empty markdown cell, so there's nothing to separate
+    let blank: Vec<_> = nb
+        .markdown_cells()
+        .filter(|cell| cell.source().iter().all(String::is_empty))
+        .collect();
+    let mut empty = true;
+    for cell in options.cells(nb.as_ref()) {
+        if blank.iter().any(|blank| std::ptr::eq(*blank, cell)) {
+            continue;
+        }
+        if !empty {
+            options.write_separator(writer)?;
         }
+        empty = false;
         match cell {
-            nbformat::v4::Cell::Code { source, .. } => {
-                writer.write_all(b"```python\n")?;
-                for line in source.iter() {
-                    writer.write_all(line.as_bytes())?;
+            nbformat::v4::Cell::Code {
+                source,
+                outputs,
+                execution_count,
+                ..
+            } => {
+                // Like Jupyter's prompt, since `[n]:` alone would be a link reference definition
+                if let Some(count) = options.execution_count(execution_count) {
+                    write!(writer, "In [{count}]:\n\n")?;
+                }
+                let fence = code_fence(source);
+                write!(writer, "{fence}python")?;
+                write_source(writer, source, "", options.wrap)?;
+                write!(writer, "\n{fence}")?;
+                for block in options.output_blocks(outputs) {
+                    let fence = code_fence(&block.lines);
+                    let info = if block.error { "pytb" } else { "text" };
+                    let lines = block.lines.join("\n");
+                    write!(writer, "\n\n{fence}{info}\n{lines}\n{fence}")?;
                 }
-                writer.write_all(b"\n```")?;
             }
             nbformat::v4::Cell::Markdown { source, .. } => {
-                for line in source.iter() {
-                    writer.write_all(line.as_bytes())?;
+                for line in source.iter().flat_map(|s| s.split_inclusive('\n')) {
+                    write_line(writer, line, "", options.wrap)?;
                 }
             }
             nbformat::v4::Cell::Raw { source, .. } => {
-                writer.write_all(b"```\n")?;
-                for line in source.iter() {
-                    writer.write_all(line.as_bytes())?;
-                }
-                writer.write_all(b"\n```")?;
+                let fence = code_fence(source);
+                write!(writer, "{fence}")?;
+                write_source(writer, source, "", options.wrap)?;
+                write!(writer, "\n{fence}")?;
             }
         }
     }
+    if !empty {
+        // End with a newline, like any POSIX text file
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Returns a backtick fence long enough to wrap the source in a fenced code block.
+///
+/// Per CommonMark, a fence is only closed by a run of backticks at least as long as the
+/// opening one, so the fence must be longer than any run of backticks in the source.
+fn code_fence(source: &[String]) -> String {
+    let longest_run = source
+        .iter()
+        .flat_map(|line| line.split(|c| c != '`'))
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    "`".repeat(longest_run.max(2) + 1)
+}
+
+/// Checks that a notebook can be written to before doing any work on it
+fn ensure_writable(path: &Path) -> Result<()> {
+    if let Err(err) = std::fs::OpenOptions::new().write(true).open(path) {
+        bail!("Cannot write to `{}`: {}", path.display(), err);
+    }
     Ok(())
 }
 
+/// Collects the environment variables for a child process.
+///
+/// Variables from `env_file` are loaded first so explicit `--env` entries take precedence.
+fn resolve_env(env: &[(String, String)], env_file: Option<&Path>) -> Result<Vec<(String, String)>> {
+    let mut envs = match env_file {
+        Some(env_file) => read_env_file(env_file)?,
+        None => vec![],
+    };
+    envs.extend(env.iter().cloned());
+    Ok(envs)
+}
+
+/// Reads `KEY=VALUE` pairs from a dotenv-style file
+///
+/// Blank lines and `#` comments are skipped, an `export ` prefix is allowed, and values
+/// may be wrapped in single or double quotes.
+fn read_env_file(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut envs = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            bail!(
+                "Invalid line {} in `{}`: expected `KEY=VALUE`",
+                i + 1,
+                path.display()
+            );
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            bail!(
+                "Invalid line {} in `{}`: missing key",
+                i + 1,
+                path.display()
+            );
+        }
+        let value = value.trim();
+        let value = [('"', '"'), ('\'', '\'')]
+            .iter()
+            .find_map(|(start, end)| value.strip_prefix(*start)?.strip_suffix(*end))
+            .unwrap_or(value);
+        envs.push((key.to_string(), value.to_string()));
+    }
+    Ok(envs)
+}
+
+/// Whether there's no display to open a browser on
+///
+/// Only detected on Linux (and other Unix-likes besides macOS), where a graphical session
+/// sets `DISPLAY` or `WAYLAND_DISPLAY`. A `BROWSER` (e.g., a text-mode one) counts as usable.
+fn is_headless() -> bool {
+    if cfg!(any(windows, target_os = "macos")) {
+        return false;
+    }
+    !has_display(|var| std::env::var_os(var))
+}
+
+/// Whether the environment, looked up with `var`, has a display or browser to open
+fn has_display(var: impl Fn(&str) -> Option<std::ffi::OsString>) -> bool {
+    ["DISPLAY", "WAYLAND_DISPLAY", "BROWSER"]
+        .iter()
+        .any(|name| var(name).is_some_and(|value| !value.is_empty()))
+}
+
+/// Whether a TCP port can be bound on the given host
+fn is_port_available(host: &str, port: u16) -> bool {
+    std::net::TcpListener::bind((host, port)).is_ok()
+}
+
 fn get_first_non_conflicting_untitled_ipybnb(directory: &Path) -> Result<PathBuf> {
     let base_name = "Untitled";
     let extension = "ipynb";
@@ -486,8 +2805,14 @@ fn get_first_non_conflicting_untitled_ipybnb(directory: &Path) -> Result<PathBuf
     bail!("Could not find an available UntitledX.ipynb");
 }
 
-fn new_notebook_with_inline_metadata(directory: &Path, python: Option<&str>) -> Result<Notebook> {
-    let temp_file = NamedTempFile::new_in(directory)?;
+fn new_notebook_with_inline_metadata(
+    directory: &Path,
+    python: Option<&str>,
+    python_preference: Option<&str>,
+    kernel: Option<&str>,
+    offline: bool,
+) -> Result<Notebook> {
+    let temp_file = temp_file_near(directory, "")?;
     let temp_path = temp_file.path().to_path_buf();
 
     let mut command = Command::new("uv");
@@ -501,6 +2826,14 @@ fn new_notebook_with_inline_metadata(directory: &Path, python: Option<&str>) ->
         command.arg("--python").arg(py);
     }
 
+    if let Some(python_preference) = python_preference {
+        command.arg("--python-preference").arg(python_preference);
+    }
+
+    if offline {
+        command.arg("--offline");
+    }
+
     let output = command.output()?;
 
     if !output.status.success() {
@@ -508,12 +2841,1015 @@ fn new_notebook_with_inline_metadata(directory: &Path, python: Option<&str>) ->
         anyhow::bail!("uv command failed: {}", stderr);
     }
 
-    Ok(NotebookBuilder::new()
+    let mut builder = NotebookBuilder::new();
+    if let Some(kernel) = kernel {
+        builder = builder.kernelspec(kernel);
+    }
+    Ok(builder
         .hidden_code_cell(&std::fs::read_to_string(temp_path)?)
         .code_cell("")
         .build())
 }
 
+/// Creates a temporary file in `dir`, or in the system's temp directory if `dir` isn't
+/// writable (e.g., a read-only checkout)
+fn temp_file_near(dir: &Path, suffix: &str) -> Result<NamedTempFile> {
+    let mut builder = tempfile::Builder::new();
+    builder.suffix(suffix);
+    builder.tempfile_in(dir).or_else(|err| {
+        builder.tempfile().map_err(|fallback_err| {
+            anyhow::anyhow!(
+                "Failed to create a temporary file in `{}` ({}) or the system temp directory ({})",
+                dir.display(),
+                err,
+                fallback_err
+            )
+        })
+    })
+}
+
+/// Whether a requirement refers to a local path (e.g., `./pkg` or `dist/pkg.whl`)
+fn looks_like_path(package: &str) -> bool {
+    package.starts_with('.')
+        || package.contains(std::path::MAIN_SEPARATOR)
+        || Path::new(package).is_absolute()
+        || [".whl", ".tar.gz", ".zip"]
+            .iter()
+            .any(|ext| package.ends_with(ext))
+}
+
+/// Rewrites a requirement that points at a local file or directory (e.g., a wheel in
+/// `./dist`) to be relative to `dir`. Other requirements are returned unchanged.
+fn local_requirement(package: &str, dir: &Path) -> Result<String> {
+    if !looks_like_path(package) || !Path::new(package).exists() {
+        return Ok(package.to_string());
+    }
+
+    let path = std::fs::canonicalize(package)?;
+    let dir = std::fs::canonicalize(dir)?;
+    let common = path
+        .components()
+        .zip(dir.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative: PathBuf = dir.components().skip(common).map(|_| "..").collect();
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative.extend(path.components().skip(common));
+    Ok(relative.to_string_lossy().into_owned())
+}
+
+/// Lists the requirements in the `dependencies` array of an inline script metadata block
+fn script_dependencies(meta: &str) -> Vec<String> {
+    let Ok(table) = metadata_toml(meta).parse::<toml::Table>() else {
+        return vec![];
+    };
+    table
+        .get("dependencies")
+        .and_then(|dependencies| dependencies.as_array())
+        .map(|dependencies| {
+            dependencies
+                .iter()
+                .filter_map(|dependency| dependency.as_str())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Provides the TOML of an inline script metadata block, without its markers and comments
+fn metadata_toml(meta: &str) -> String {
+    let lines: Vec<&str> = meta.lines().collect();
+    // The block starts with `# /// script` and ends with `# ///`
+    let content = lines
+        .get(1..lines.len().saturating_sub(1))
+        .unwrap_or_default();
+    content
+        .iter()
+        .filter_map(|line| line.strip_prefix('#'))
+        .map(|line| format!("{}\n", line.strip_prefix(' ').unwrap_or(line)))
+        .collect()
+}
+
+/// Finds the `requires-python` of an inline script metadata block
+fn script_requires_python(meta: &str) -> Option<String> {
+    let table: toml::Table = metadata_toml(meta).parse().ok()?;
+    table.get("requires-python")?.as_str().map(str::to_string)
+}
+
+/// Wraps TOML in an inline script metadata block, the inverse of [`metadata_toml`]
+fn metadata_block(toml: &str) -> String {
+    let mut block = String::from("# /// script\n");
+    for line in toml.trim_end().lines() {
+        if line.is_empty() {
+            block.push_str("#\n");
+        } else {
+            block.push_str(&format!("# {line}\n"));
+        }
+    }
+    block.push_str("# ///");
+    block
+}
+
+/// Warns about `--with` requirements for packages the notebook already depends on.
+///
+/// uv layers `--with` requirements over the notebook's environment, so they take precedence
+/// over the inline metadata for the session, which can be surprising.
+fn warn_shadowed_dependencies(printer: &Printer, meta: &str, with: &[String]) -> Result<()> {
+    let dependencies = script_dependencies(meta);
+    for with_item in with {
+        let Some(name) = requirement_name(with_item) else {
+            continue;
+        };
+        for dependency in &dependencies {
+            if dependency.trim() == with_item.trim()
+                || requirement_name(dependency).as_ref() != Some(&name)
+            {
+                continue;
+            }
+            writeln!(
+                printer.stderr(),
+                "{}: `{}` takes precedence over `{}` from the notebook's inline metadata for this session",
+                "warning".yellow().bold(),
+                format!("--with {}", with_item.trim()).cyan(),
+                dependency.cyan()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Provides the normalized (PEP 503) package name of a requirement like `Polars[all]>=1`
+///
+/// Returns `None` for requirements that aren't named, like paths and URLs.
+fn requirement_name(requirement: &str) -> Option<String> {
+    let name = REQUIREMENT_NAME_REGEX.find(requirement.trim())?.as_str();
+    let rest = requirement.trim()[name.len()..].trim_start();
+    if rest.starts_with(['/', '\\', ':', '+']) {
+        return None;
+    }
+    Some(
+        name.split(['-', '_', '.'])
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-")
+            .to_lowercase(),
+    )
+}
+
+/// Whether a requirement is just a package name (and extras), without a version or source
+fn is_bare_requirement(requirement: &str) -> bool {
+    let requirement = requirement.trim();
+    REQUIREMENT_NAME_REGEX
+        .find(requirement)
+        .is_some_and(|name| {
+            let rest = &requirement[name.end()..];
+            rest.is_empty() || (rest.starts_with('[') && rest.ends_with(']'))
+        })
+}
+
+static REQUIREMENT_NAME_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z0-9]([A-Za-z0-9._-]*[A-Za-z0-9])?").unwrap());
+
+/// Reads the parameters for `juv exec` from a YAML (or JSON) file of names and values
+fn read_params_file(path: &Path) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("Failed to read `{}`: {}", path.display(), err))?;
+    match serde_norway::from_str(&contents) {
+        Ok(serde_json::Value::Object(params)) => Ok(params),
+        Ok(serde_json::Value::Null) => Ok(serde_json::Map::new()),
+        Ok(_) => bail!("`{}` must contain a mapping of parameters", path.display()),
+        Err(err) => bail!("Failed to parse `{}`: {}", path.display(), err),
+    }
+}
+
+/// Interprets a `--param` value the way papermill does, falling back to a string
+fn parse_param_value(value: &str) -> serde_json::Value {
+    match value {
+        "True" => true.into(),
+        "False" => false.into(),
+        "None" => serde_json::Value::Null,
+        _ => value
+            .parse::<i64>()
+            .map(serde_json::Value::from)
+            .or_else(|_| value.parse::<f64>().map(serde_json::Value::from))
+            .unwrap_or_else(|_| value.into()),
+    }
+}
+
+/// Whether a name can be assigned to in Python, i.e., it's an identifier but not a keyword
+fn is_python_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c == '_' || c.is_alphabetic())
+        && chars.all(|c| c == '_' || c.is_alphanumeric())
+        && !PYTHON_KEYWORDS.contains(&name)
+}
+
+/// Python's (hard) keywords. Soft keywords like `match` and `type` are fine as names.
+const PYTHON_KEYWORDS: [&str; 35] = [
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+/// Formats a parameter value as a Python literal
+fn python_literal(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "None".to_string(),
+        serde_json::Value::Bool(true) => "True".to_string(),
+        serde_json::Value::Bool(false) => "False".to_string(),
+        serde_json::Value::Number(number) => number.to_string(),
+        // JSON string escapes are also valid in Python string literals
+        serde_json::Value::String(_) => value.to_string(),
+        serde_json::Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(python_literal).collect();
+            format!("[{}]", items.join(", "))
+        }
+        serde_json::Value::Object(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{}: {}",
+                        serde_json::Value::from(key.as_str()),
+                        python_literal(value)
+                    )
+                })
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+    }
+}
+
+/// Checks that the requirements for a runtime can be resolved, exiting with uv's reason if not
+fn ensure_installable(
+    printer: &Printer,
+    requirements: &[String],
+    python: Option<&str>,
+    offline: bool,
+) -> Result<()> {
+    let mut command = Command::new("uv");
+    command.args(["pip", "compile", "--quiet", "--no-header"]);
+    if let Some(python) = python {
+        command.arg("--python").arg(python);
+    }
+    if offline {
+        command.arg("--offline");
+    }
+    let mut child = command
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    {
+        // Taking stdin closes it once the requirements are written
+        let mut stdin = child.stdin.take().expect("Failed to open stdin");
+        // One per line, since a requirement may itself contain commas (e.g., `pkg>=4,<5`)
+        stdin.write_all(requirements.join("\n").as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        writeln!(
+            printer.stderr(),
+            "{}: The runtime `{}` can't be installed\n\n{}",
+            "error".red().bold(),
+            requirements.join(",").cyan(),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )?;
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The oldest uv with everything juv relies on (e.g., `uv init --script` and `uv add --script`)
+const MIN_UV_VERSION: &str = "0.5.0";
+
+/// The first uv that can lock scripts (`uv lock --script`)
+const MIN_UV_VERSION_FOR_LOCK: &str = "0.5.17";
+
+/// The version of the uv on the `PATH`, if it could be determined
+///
+/// Only looked up once, since several commands check it.
+static UV_VERSION: Lazy<Option<pep440_rs::Version>> = Lazy::new(|| {
+    let output = Command::new("uv").arg("--version").output().ok()?;
+    // e.g., `uv 0.5.17 (1d6e9e5a1 2025-01-09)`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.split_whitespace().nth(1)?.parse().ok()
+});
+
+/// Warns when uv is older than `min`, or fails with `strict`
+///
+/// Nothing is reported if uv's version can't be determined, since running uv reports a
+/// missing uv more clearly.
+fn check_uv_version(printer: &Printer, min: &str, strict: bool) -> Result<()> {
+    let Some(version) = UV_VERSION.as_ref() else {
+        return Ok(());
+    };
+    let min: pep440_rs::Version = min.parse()?;
+    if *version >= min {
+        return Ok(());
+    }
+    let message = format!(
+        "uv {} is older than {}, which juv needs for this command. Run `{}` to upgrade.",
+        version.to_string().cyan(),
+        min.to_string().cyan(),
+        "uv self update".yellow().bold()
+    );
+    if strict {
+        writeln!(printer.stderr(), "{}: {}", "error".red().bold(), message)?;
+        std::process::exit(1);
+    }
+    writeln!(
+        printer.stderr(),
+        "{}: {}",
+        "warning".yellow().bold(),
+        message
+    )?;
+    Ok(())
+}
+
+/// Checks that a `--python` version satisfies the notebook's `requires-python`, exiting
+/// before uv spends time setting up an environment for it if not
+///
+/// Only plain versions (e.g., `3.10` or `3.10.4`) are checked, since paths and other requests
+/// don't say which version they are. A version without a patch number is accepted if any of
+/// its patch releases could be.
+fn ensure_python_satisfies(
+    printer: &Printer,
+    python: Option<&str>,
+    meta: Option<&str>,
+) -> Result<()> {
+    let Some(python) = python.filter(|python| PYTHON_VERSION_REGEX.is_match(python)) else {
+        return Ok(());
+    };
+    let Some(requires_python) = meta.and_then(script_requires_python) else {
+        return Ok(());
+    };
+    // uv reports an invalid `requires-python` itself
+    let Ok(specifiers) = requires_python.parse::<pep440_rs::VersionSpecifiers>() else {
+        return Ok(());
+    };
+    let version: pep440_rs::Version = python.parse()?;
+    let candidates = match *version.release() {
+        [major, minor] => vec![
+            pep440_rs::Version::new([major, minor, 0]),
+            pep440_rs::Version::new([major, minor, u64::from(u32::MAX)]),
+        ],
+        _ => vec![version],
+    };
+    if candidates
+        .iter()
+        .any(|candidate| specifiers.contains(candidate))
+    {
+        return Ok(());
+    }
+    writeln!(
+        printer.stderr(),
+        "{}: Python {} doesn't satisfy the notebook's `requires-python` ({})",
+        "error".red().bold(),
+        python.cyan(),
+        requires_python.cyan()
+    )?;
+    std::process::exit(1);
+}
+
+/// Whether the minimum Python allowed by `requires-python` went up between two values
+///
+/// Only lower bounds count, so narrowing the upper end (e.g., adding `<4`) doesn't.
+fn raises_requires_python(before: Option<&str>, after: Option<&str>) -> bool {
+    let minimum = |requires_python: Option<&str>| {
+        let specifiers: pep440_rs::VersionSpecifiers = requires_python?.parse().ok()?;
+        specifiers
+            .iter()
+            .filter(|specifier| {
+                matches!(
+                    specifier.operator(),
+                    pep440_rs::Operator::GreaterThanEqual
+                        | pep440_rs::Operator::GreaterThan
+                        | pep440_rs::Operator::Equal
+                        | pep440_rs::Operator::EqualStar
+                        | pep440_rs::Operator::ExactEqual
+                        | pep440_rs::Operator::TildeEqual
+                )
+            })
+            .map(|specifier| specifier.version().clone())
+            .max()
+    };
+    match (minimum(before), minimum(after)) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(before), Some(after)) => after > before,
+    }
+}
+
+static PYTHON_VERSION_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d+\.\d+(\.\d+)?$").unwrap());
+
+/// Resolves a runtime specifier, falling back to the runtimes registered in `juv.toml`
+fn resolve_runtime(specifier: &str) -> Result<Runtime> {
+    match specifier.parse::<Runtime>() {
+        Ok(runtime) => Ok(runtime),
+        Err(err) => {
+            let mut config = Config::discover(&std::env::current_dir()?)?;
+            match config.runtimes.remove(specifier) {
+                Some(runtime) => Ok(Runtime::custom(runtime)),
+                None => Err(err),
+            }
+        }
+    }
+}
+
+/// Finds the inline script metadata (PEP 723) block in the notebook's code cells
+fn find_inline_metadata(nb: &Notebook) -> Option<String> {
+    nb.code_cells().find_map(|cell| {
+        if let nbformat::v4::Cell::Code { source, .. } = cell {
+            PEP723_REGEX
+                .captures(&join_source(source))
+                .and_then(|cap| cap.get(0).map(|m| m.as_str().to_string()))
+        } else {
+            None
+        }
+    })
+}
+
 static PEP723_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"(?m)^# /// (?P<type>[a-zA-Z0-9-]+)$\s(?P<content>(^#(| .*)$\s)+)^# ///$").unwrap()
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Copies a notebook from `tests/fixtures` into a temporary directory
+    fn fixture(name: &str) -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        std::fs::copy(source, &path).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn execution_record_goes_into_the_exported_notebook() {
+        let (dir, executed) = fixture("executed.ipynb");
+        let output = dir.path().join("executed.out.ipynb");
+        let record = ExecutionRecord {
+            start_time: "2024-01-01T00:00:00Z".to_string(),
+            end_time: "2024-01-01T00:00:02Z".to_string(),
+            duration: 2.0,
+            exception: None,
+            input_path: "analysis.ipynb".to_string(),
+            output_path: output.to_string_lossy().into_owned(),
+            parameters: serde_json::Map::new(),
+            environment_variables: serde_json::Map::new(),
+            version: env!("CARGO_PKG_VERSION"),
+        };
+        let before = std::fs::read_to_string(&executed).unwrap();
+        write_executed(&executed, ExportFormat::Notebook, &output, Some(record)).unwrap();
+        assert_eq!(std::fs::read_to_string(&executed).unwrap(), before);
+
+        let nb = Notebook::from_path(&output).unwrap();
+        let papermill = &nb.as_ref().metadata.additional["papermill"];
+        assert_eq!(papermill["duration"], 2.0);
+        assert_eq!(papermill["input_path"], "analysis.ipynb");
+        assert_eq!(nb.cells().len(), 3);
+    }
+
+    #[test]
+    fn exports_match_cat() {
+        let (dir, executed) = fixture("executed.ipynb");
+        let render_options = RenderOptions {
+            outputs: true,
+            ..RenderOptions::default()
+        };
+        for (format, script, output) in [
+            (ExportFormat::Script, true, "# Output:"),
+            (ExportFormat::Markdown, false, "```text"),
+        ] {
+            let exported = dir
+                .path()
+                .join("exported")
+                .with_extension(format.extension());
+            write_executed(&executed, format, &exported, None).unwrap();
+            let printed = dir.path().join("printed");
+            cat(
+                &Printer::Quiet,
+                std::slice::from_ref(&executed),
+                script,
+                None,
+                Some(&printed),
+                false,
+                render_options,
+            )
+            .unwrap();
+            let exported = std::fs::read_to_string(exported).unwrap();
+            assert_eq!(exported, std::fs::read_to_string(printed).unwrap());
+            assert!(exported.contains(output), "{exported}");
+        }
+    }
+
+    #[test]
+    fn record_metadata_requires_an_executed_notebook() {
+        let (_dir, path) = fixture("executed.ipynb");
+        let before = std::fs::read_to_string(&path).unwrap();
+        for export in [None, Some(ExportFormat::Html)] {
+            let options = ExecOptions {
+                export,
+                record_metadata: true,
+                project: Project::None,
+                ..ExecOptions::default()
+            };
+            let err = exec(&Printer::Quiet, &path, options).unwrap_err();
+            assert!(err.to_string().contains("--to notebook"), "{err}");
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn output_dir_mirrors_the_working_directory() {
+        let cwd = std::env::current_dir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        for (format, expected) in [
+            (ExportFormat::Notebook, "reports/a.ipynb"),
+            (ExportFormat::Html, "reports/a.html"),
+        ] {
+            let output = mirrored_output(&cwd.join("reports/a.ipynb"), out.path(), format).unwrap();
+            assert_eq!(output, out.path().join(expected));
+        }
+
+        // Only the file name would be left, which other notebooks may share
+        let outside = tempfile::tempdir().unwrap();
+        let err = mirrored_output(
+            &outside.path().join("a.ipynb"),
+            out.path(),
+            ExportFormat::Notebook,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("outside the working directory"));
+
+        let err = mirrored_output(&cwd.join("a.ipynb"), Path::new("."), ExportFormat::Notebook)
+            .unwrap_err();
+        assert!(err.to_string().contains("overwrite the notebook itself"));
+    }
+
+    #[test]
+    fn nbformat_minor_must_represent_the_cells() {
+        let (_dir, path) = fixture("executed.ipynb");
+        let before = std::fs::read_to_string(&path).unwrap();
+        let targets = [path.to_string_lossy().into_owned()];
+        let err = fmt(&Printer::Quiet, &targets, false, CheckFormat::Text, Some(4)).unwrap_err();
+        assert!(err.to_string().contains("can't be set to 4.4"), "{err}");
+        let err = clear(
+            &Printer::Quiet,
+            &targets,
+            false,
+            CheckFormat::Text,
+            ClearPolicy::default(),
+            None,
+            Some(4),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("can't be set to 4.4"), "{err}");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+
+        let (_dir, path) = fixture("legacy.ipynb");
+        let targets = [path.to_string_lossy().into_owned()];
+        fmt(&Printer::Quiet, &targets, false, CheckFormat::Text, Some(5)).unwrap();
+        assert_eq!(Notebook::read_nbformat_minor(&path).unwrap(), 5);
+    }
+
+    #[test]
+    fn server_root_outside_the_working_directory() {
+        assert_eq!(server_root(Path::new("analysis.ipynb")).unwrap(), None);
+        assert_eq!(
+            server_root(Path::new("reports/analysis.ipynb")).unwrap(),
+            None
+        );
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            server_root(&dir.path().join("analysis.ipynb"))
+                .unwrap()
+                .as_deref(),
+            Some(dir.path())
+        );
+    }
+
+    #[test]
+    fn code_fences_outlast_backticks_in_the_source() {
+        let lines = |lines: &[&str]| lines.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(code_fence(&lines(&["x = 1"])), "```");
+        assert_eq!(code_fence(&lines(&["`a` and ``b``"])), "```");
+        assert_eq!(
+            code_fence(&lines(&["doc = \"\"\"\n", "```python\n", "\"\"\""])),
+            "````"
+        );
+        assert_eq!(code_fence(&lines(&["`````"])), "``````");
+
+        let nb = NotebookBuilder::new()
+            .code_cell("doc = \"\"\"\n```python\nx = 1\n```\n\"\"\"")
+            .build();
+        let mut markdown = vec![];
+        write_markdown(&mut markdown, &nb, RenderOptions::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(markdown).unwrap(),
+            "````python\ndoc = \"\"\"\n```python\nx = 1\n```\n\"\"\"\n````\n"
+        );
+    }
+
+    #[test]
+    fn init_refuses_to_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("existing.ipynb");
+        std::fs::write(&path, "{}").unwrap();
+        let err = ensure_new_notebook(&path).unwrap_err();
+        assert!(err.to_string().contains("Pass `--force`"), "{err}");
+        ensure_new_notebook(&dir.path().join("new.ipynb")).unwrap();
+    }
+
+    #[test]
+    fn local_requirements_are_relative_to_the_notebook() {
+        let dir = tempfile::tempdir().unwrap();
+        let notebooks = dir.path().join("notebooks");
+        std::fs::create_dir_all(dir.path().join("dist")).unwrap();
+        std::fs::create_dir_all(&notebooks).unwrap();
+        let wheel = dir.path().join("dist/pkg-0.1-py3-none-any.whl");
+        std::fs::write(&wheel, "").unwrap();
+        let local = notebooks.join("pkg-0.1.tar.gz");
+        std::fs::write(&local, "").unwrap();
+
+        let relative =
+            |package: &Path| local_requirement(&package.to_string_lossy(), &notebooks).unwrap();
+        assert_eq!(
+            Path::new(&relative(&wheel)),
+            Path::new("../dist/pkg-0.1-py3-none-any.whl")
+        );
+        assert_eq!(Path::new(&relative(&local)), Path::new("./pkg-0.1.tar.gz"));
+        assert_eq!(Path::new(&relative(dir.path())), Path::new(".."));
+
+        for package in ["numpy>=2", "./missing.whl"] {
+            assert_eq!(local_requirement(package, &notebooks).unwrap(), package);
+        }
+    }
+
+    #[test]
+    fn empty_cells_render_consistently() {
+        let nb = Notebook::from_path(&fixture("empty-cells.ipynb").1).unwrap();
+        let render = |script: bool, skip_empty: bool| {
+            let options = RenderOptions {
+                skip_empty,
+                ..RenderOptions::default()
+            };
+            let mut contents = vec![];
+            if script {
+                write_script(&mut contents, nb.as_ref(), options).unwrap();
+            } else {
+                write_markdown(&mut contents, &nb, options).unwrap();
+            }
+            String::from_utf8(contents).unwrap()
+        };
+        assert_eq!(
+            render(true, false),
+            "# %% [markdown]\n# # Title\n\n# %%\n\n# %% [markdown]\n\n# %%\n   \n\n# %%\nx = 1\n"
+        );
+        assert_eq!(
+            render(false, false),
+            "# Title\n\n```python\n```\n\n```python\n   \n```\n\n```python\nx = 1\n```\n"
+        );
+        assert_eq!(
+            render(true, true),
+            "# %% [markdown]\n# # Title\n\n# %%\nx = 1\n"
+        );
+        assert_eq!(render(false, true), "# Title\n\n```python\nx = 1\n```\n");
+    }
+
+    #[test]
+    fn exec_skips_notebooks_without_code_cells() {
+        for name in ["no-cells.ipynb", "markdown-only.ipynb"] {
+            let (dir, path) = fixture(name);
+            let options = ExecOptions {
+                export: Some(ExportFormat::Html),
+                project: Project::None,
+                ..ExecOptions::default()
+            };
+            // Returns before uv is ever invoked
+            exec(&Printer::Quiet, &path, options).unwrap();
+            let files = std::fs::read_dir(dir.path()).unwrap().count();
+            assert_eq!(files, 1, "{name}");
+        }
+    }
+
+    #[test]
+    fn add_rejects_a_package_containing_the_notebook() {
+        let (dir, path) = fixture("executed.ipynb");
+        let before = std::fs::read_to_string(&path).unwrap();
+        let package = dir.path().to_string_lossy().into_owned();
+        assert!(looks_like_path(&package));
+        let packages = [package];
+        let options = AddOptions {
+            packages: &packages,
+            strict: true,
+            ..AddOptions::default()
+        };
+        let err = add(&Printer::Quiet, &path, options).unwrap_err();
+        assert!(err.to_string().contains("contains the notebook"), "{err}");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+
+        // The notebook's path is resolved from the project, like for `juv run`
+        let options = AddOptions {
+            packages: &packages,
+            strict: true,
+            project: Project::new(Some(dir.path()), false).unwrap(),
+            ..AddOptions::default()
+        };
+        let err = add(&Printer::Quiet, Path::new("executed.ipynb"), options).unwrap_err();
+        assert!(err.to_string().contains("contains the notebook"), "{err}");
+
+        for package in ["numpy", "numpy>=2", "pkg[extra]"] {
+            assert!(!looks_like_path(package), "{package}");
+        }
+        for package in ["./pkg", "../pkg", "dist/pkg.whl", "pkg-0.1.tar.gz"] {
+            assert!(looks_like_path(package), "{package}");
+        }
+    }
+
+    #[test]
+    fn displays() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.into())
+            }
+        };
+        assert!(!has_display(env(&[])));
+        assert!(!has_display(env(&[("DISPLAY", ""), ("TERM", "xterm")])));
+        assert!(has_display(env(&[("DISPLAY", ":0")])));
+        assert!(has_display(env(&[("WAYLAND_DISPLAY", "wayland-0")])));
+        assert!(has_display(env(&[("BROWSER", "w3m")])));
+    }
+
+    #[test]
+    fn temp_files_fall_back_to_the_system_temp_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = temp_file_near(dir.path(), ".py").unwrap();
+        assert_eq!(file.path().parent(), Some(dir.path()));
+        assert!(file.path().to_string_lossy().ends_with(".py"));
+
+        // A missing directory fails like a read-only one, even when running as root
+        let missing = dir.path().join("missing");
+        let file = temp_file_near(&missing, ".py").unwrap();
+        assert_eq!(file.path().parent(), Some(std::env::temp_dir().as_path()));
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn cleared_notebooks_are_formatted() {
+        for policy in [
+            ClearPolicy::default(),
+            ClearPolicy {
+                outputs: ClearOutputs::Images,
+                execution_count: false,
+            },
+        ] {
+            let (_dir, path) = fixture("executed.ipynb");
+            let targets = [path.to_string_lossy().into_owned()];
+            clear(
+                &Printer::Quiet,
+                &targets,
+                false,
+                CheckFormat::Text,
+                policy,
+                None,
+                None,
+            )
+            .unwrap();
+            let (_, changed) = format_notebook(&path, None).unwrap();
+            assert!(!changed, "`juv fmt` would change a cleared notebook");
+        }
+    }
+
+    #[test]
+    fn project_root_prefers_the_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let member = root.join("packages").join("member");
+        std::fs::create_dir_all(member.join("src")).unwrap();
+        std::fs::write(root.join("pyproject.toml"), "").unwrap();
+        std::fs::write(member.join("pyproject.toml"), "").unwrap();
+
+        // Without a lock, the nearest project wins
+        assert_eq!(find_project_root(&member.join("src")), Some(member.clone()));
+        // Workspace members share the lock at the workspace root
+        std::fs::write(root.join("uv.lock"), "").unwrap();
+        assert_eq!(
+            find_project_root(&member.join("src")),
+            Some(root.to_path_buf())
+        );
+        assert_eq!(find_project_root(Path::new("/")), None);
+    }
+
+    #[test]
+    fn resolve_notebook_from_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let notebooks = dir.path().join("notebooks");
+        std::fs::create_dir(&notebooks).unwrap();
+        std::fs::write(notebooks.join("a.ipynb"), "").unwrap();
+        let relative = Path::new("notebooks/a.ipynb");
+
+        let project = Project::new(Some(dir.path()), false).unwrap();
+        assert_eq!(
+            project.resolve_notebook(relative).unwrap(),
+            notebooks.join("a.ipynb")
+        );
+        // Paths that don't exist in the project either are left for the error to name
+        assert_eq!(
+            project.resolve_notebook(Path::new("b.ipynb")).unwrap(),
+            Path::new("b.ipynb")
+        );
+        assert_eq!(Project::None.resolve_notebook(relative).unwrap(), relative);
+    }
+
+    #[test]
+    fn parameter_names() {
+        for name in ["alpha", "_private", "x1", "données", "match", "type"] {
+            assert!(is_python_identifier(name), "{name}");
+        }
+        for name in [
+            "", "1x", "a-b", "a b", "class", "None", "lambda", "True", "await",
+        ] {
+            assert!(!is_python_identifier(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn params_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = dir.path().join("params.yaml");
+        std::fs::write(
+            &yaml,
+            "alpha: 0.5\nname: run 1\nflags: [true, null]\nnested:\n  n: 3\n",
+        )
+        .unwrap();
+        let params = read_params_file(&yaml).unwrap();
+        assert_eq!(
+            serde_json::Value::Object(params),
+            serde_json::json!({
+                "alpha": 0.5,
+                "name": "run 1",
+                "flags": [true, null],
+                "nested": {"n": 3},
+            })
+        );
+
+        let json = dir.path().join("params.json");
+        std::fs::write(&json, r#"{"ratio": 1e3, "label": "a\"b"}"#).unwrap();
+        let params = read_params_file(&json).unwrap();
+        assert_eq!(params["ratio"], 1000.0);
+        assert_eq!(python_literal(&params["label"]), r#""a\"b""#);
+
+        let empty = dir.path().join("empty.yaml");
+        std::fs::write(&empty, "").unwrap();
+        assert!(read_params_file(&empty).unwrap().is_empty());
+
+        let list = dir.path().join("list.yaml");
+        std::fs::write(&list, "- 1\n- 2\n").unwrap();
+        assert!(read_params_file(&list).is_err());
+    }
+
+    #[test]
+    fn parameter_values() {
+        assert_eq!(parse_param_value("True"), true);
+        assert_eq!(parse_param_value("None"), serde_json::Value::Null);
+        assert_eq!(parse_param_value("42"), 42);
+        assert_eq!(parse_param_value("0.5"), 0.5);
+        assert_eq!(parse_param_value("true"), "true");
+        assert_eq!(
+            python_literal(&serde_json::json!({"a": [1, null, false], "b": "x"})),
+            r#"{"a": [1, None, False], "b": "x"}"#
+        );
+    }
+
+    #[test]
+    fn script_dependencies_reads_the_toml() {
+        let meta = r#"# /// script
+# requires-python = ">=3.12"
+# dependencies = [
+#     "polars>=1",  # dataframes
+#     'anywidget[dev]',
+#     "numpy; python_version < '3.13'",
+# ]
+#
+# [tool.uv]
+# dependencies = ["not-a-dependency"]
+# ///"#;
+        assert_eq!(
+            script_dependencies(meta),
+            [
+                "polars>=1",
+                "anywidget[dev]",
+                "numpy; python_version < '3.13'"
+            ]
+        );
+        assert!(script_dependencies("# /// script\n# dependencies = [\n# ///").is_empty());
+        assert!(
+            script_dependencies("# /// script\n# requires-python = \">=3.12\"\n# ///").is_empty()
+        );
+    }
+
+    #[test]
+    fn cat_to_file_strips_colors() {
+        let (dir, path) = fixture("error.ipynb");
+        let output = dir.path().join("error.md");
+        cat(
+            &Printer::Quiet,
+            &[path],
+            false,
+            None,
+            Some(&output),
+            false,
+            RenderOptions {
+                outputs: true,
+                ..RenderOptions::default()
+            },
+        )
+        .unwrap();
+        let contents = std::fs::read_to_string(output).unwrap();
+        assert!(!contents.contains('\x1b'), "{contents:?}");
+        assert!(contents.contains("ZeroDivisionError: division by zero"));
+        assert!(contents.contains("----> 1 1 / 0"));
+    }
+
+    #[test]
+    fn cat_separates_notebooks_with_comments() {
+        let (dir, first) = fixture("markdown-only.ipynb");
+        let second = dir.path().join("copy.ipynb");
+        std::fs::copy(&first, &second).unwrap();
+        let output = dir.path().join("notebooks.txt");
+        let files = [first, second];
+        for (script, separator) in [
+            (false, "<!-- ===== {} ===== -->"),
+            (true, "# ===== {} ====="),
+        ] {
+            cat(
+                &Printer::Quiet,
+                &files,
+                script,
+                None,
+                Some(&output),
+                false,
+                RenderOptions::default(),
+            )
+            .unwrap();
+            let contents = std::fs::read_to_string(&output).unwrap();
+            for file in &files {
+                let line = separator.replace("{}", &file.display().to_string());
+                assert_eq!(
+                    contents.matches(&format!("{line}\n")).count(),
+                    1,
+                    "{contents}"
+                );
+            }
+            assert_eq!(contents.contains("# ====="), script, "{contents}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn edit_cell_without_stored_ids() {
+        let (_dir, path) = fixture("legacy.ipynb");
+        edit_cell(
+            &Printer::Quiet,
+            &path,
+            Some("sed -i s/x/y/g"),
+            Some(false),
+            1,
+        )
+        .unwrap();
+        let nb = Notebook::from_path(&path).unwrap();
+        assert_eq!(nb.cells()[1].source(), ["y = 6\n", "print(y)"]);
+        assert_eq!(
+            nb.cells()[0].source(),
+            ["# Legacy\n", "A notebook saved before cells had ids."]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn edit_cell_with_stored_ids() {
+        let (_dir, path) = fixture("executed.ipynb");
+        edit_cell(
+            &Printer::Quiet,
+            &path,
+            Some("sed -i s/hello/goodbye/"),
+            Some(false),
+            0,
+        )
+        .unwrap();
+        let nb = Notebook::from_path(&path).unwrap();
+        assert_eq!(nb.cells()[0].source(), ["print(\"goodbye\")"]);
+    }
+}
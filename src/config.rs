@@ -0,0 +1,90 @@
+use anyhow::Result;
+use etcetera::BaseStrategy;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The name of a project's configuration file
+const PROJECT_CONFIG_FILE: &str = "juv.toml";
+
+/// Settings read from a `juv.toml` (or the global `config.toml`)
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// The pager for `juv cat`
+    pub pager: Option<String>,
+    /// A template for the script `juv run` launches Jupyter with, relative to the config file
+    pub run_template: Option<PathBuf>,
+    /// Runtimes for `juv run`, in addition to the built-in Jupyter frontends
+    #[serde(default)]
+    pub runtimes: HashMap<String, CustomRuntime>,
+}
+
+/// A frontend launched by running an executable with the notebook as an argument
+///
+/// ```toml
+/// [runtimes.nteract]
+/// command = "nteract"
+/// args = ["--new-window"]
+/// with = ["ipykernel"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomRuntime {
+    /// The executable to run
+    pub command: String,
+    /// Arguments to pass before the notebook
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Packages to install alongside the notebook's dependencies (e.g., a kernel)
+    #[serde(default)]
+    pub with: Vec<String>,
+}
+
+impl Config {
+    /// Reads the global configuration and the nearest `juv.toml` in `dir` or its ancestors.
+    ///
+    /// Settings from the project take precedence over global ones. The global configuration
+    /// lives at `juv/config.toml` in the user's config directory (e.g.,
+    /// `~/.config/juv/config.toml`, or `%APPDATA%\juv\config.toml` on Windows).
+    pub fn discover(dir: &Path) -> Result<Self> {
+        let global = match global_config_path() {
+            Some(path) if path.is_file() => Self::from_path(&path)?,
+            _ => Self::default(),
+        };
+        let project = dir
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+            .find(|path| path.is_file());
+        match project {
+            Some(path) => Ok(global.merge(Self::from_path(&path)?)),
+            None => Ok(global),
+        }
+    }
+
+    /// Reads the configuration from a file
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut config: Self = toml::from_str(&contents)
+            .map_err(|err| anyhow::anyhow!("Failed to parse `{}`: {}", path.display(), err))?;
+        if let (Some(template), Some(dir)) = (&config.run_template, path.parent()) {
+            config.run_template = Some(dir.join(template));
+        }
+        Ok(config)
+    }
+
+    /// Combines two configurations, preferring the settings of `other`
+    fn merge(mut self, other: Self) -> Self {
+        self.pager = other.pager.or(self.pager);
+        self.run_template = other.run_template.or(self.run_template);
+        self.runtimes.extend(other.runtimes);
+        self
+    }
+}
+
+/// The path of the global configuration, following the XDG convention on all platforms but
+/// Windows (like uv)
+fn global_config_path() -> Option<PathBuf> {
+    let strategy = etcetera::choose_base_strategy().ok()?;
+    Some(strategy.config_dir().join("juv").join("config.toml"))
+}
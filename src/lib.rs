@@ -0,0 +1,3 @@
+//! The notebook model behind the `juv` CLI, exposed as a library for the benchmarks
+
+pub mod notebook;
@@ -0,0 +1,94 @@
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{File, TryLockError};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long to wait for another juv process to release a notebook
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An advisory lock that serializes read-modify-write cycles on a notebook.
+///
+/// Lock files live in `<temp dir>/juv/locks/`, named after a SHA-256 hash of the notebook's
+/// absolute path, so they never clutter the notebook's directory. The lock is released
+/// when the guard is dropped. If another process holds the lock for longer than 30
+/// seconds, acquiring it fails with an error naming the lock file.
+pub struct NotebookLock {
+    _file: File,
+}
+
+impl NotebookLock {
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let path = std::fs::canonicalize(path).or_else(|_| std::path::absolute(path))?;
+        let dir = std::env::temp_dir().join("juv").join("locks");
+        std::fs::create_dir_all(&dir)?;
+        let lock_path = dir.join(lock_file_name(&path));
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+
+        let start = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Self { _file: file }),
+                Err(TryLockError::WouldBlock) if start.elapsed() < LOCK_TIMEOUT => {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(TryLockError::WouldBlock) => bail!(
+                    "Timed out waiting for another process to release `{}` (lock file: `{}`)",
+                    path.display(),
+                    lock_path.display()
+                ),
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Names the lock file of a notebook after a hash of its absolute path
+///
+/// The hash must be the same for every juv version (and build) that may run at once.
+fn lock_file_name(path: &Path) -> String {
+    let digest = Sha256::digest(path.as_os_str().as_encoded_bytes());
+    let hex: String = digest[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("{hex}.lock")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_file_names_are_stable() {
+        // echo -n /notebooks/analysis.ipynb | sha256sum
+        assert_eq!(
+            lock_file_name(Path::new("/notebooks/analysis.ipynb")),
+            "7ba254981587fad5.lock"
+        );
+        assert_ne!(
+            lock_file_name(Path::new("/notebooks/a.ipynb")),
+            lock_file_name(Path::new("/notebooks/b.ipynb"))
+        );
+    }
+
+    #[test]
+    fn lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.ipynb");
+        std::fs::write(&path, "{}").unwrap();
+        let lock = NotebookLock::acquire(&path).unwrap();
+        let lock_path = std::env::temp_dir()
+            .join("juv")
+            .join("locks")
+            .join(lock_file_name(&std::fs::canonicalize(&path).unwrap()));
+        let file = File::options().write(true).open(&lock_path).unwrap();
+        assert!(matches!(file.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(lock);
+        assert!(file.try_lock().is_ok());
+    }
+}
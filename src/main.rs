@@ -1,14 +1,19 @@
 use anyhow::Result;
 use clap::builder::styling::{AnsiColor, Effects};
 use clap::builder::Styles;
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use owo_colors::OwoColorize;
+use std::fmt::Write as _;
 use std::io::Write as _;
 
 mod commands;
-mod notebook;
+mod config;
+mod lock;
 mod printer;
 mod script;
 
+use juv::notebook;
+
 // Configures Clap v3-style help menu colors
 const STYLES: Styles = Styles::styled()
     .header(AnsiColor::Yellow.on_default().effects(Effects::BOLD))
@@ -19,6 +24,11 @@ const STYLES: Styles = Styles::styled()
 #[derive(Parser)]
 #[command(name = "juv", author, long_version = version())]
 #[command(about = "A fast toolkit for reproducible Jupyter notebooks")]
+#[command(long_about = "A fast toolkit for reproducible Jupyter notebooks
+
+Commands that take a notebook default to the `JUV_NOTEBOOK` environment variable when \
+it's omitted. Relative paths that don't exist from the working directory are looked up \
+from the root of the enclosing uv workspace, unless `--no-project` is given.")]
 #[command(styles=STYLES)]
 struct Cli {
     #[command(subcommand)]
@@ -29,6 +39,75 @@ struct Cli {
     /// Suppress all output
     #[arg(short, long, action, conflicts_with = "verbose", global = true)]
     quiet: bool,
+    /// Change to the given directory before running the command
+    ///
+    /// Relative notebook paths are resolved against this directory.
+    #[arg(long, global = true)]
+    directory: Option<std::path::PathBuf>,
+    /// Disable network access, relying on uv's cache for dependencies
+    ///
+    /// Forwarded to every uv invocation. Commands that don't need uv are unaffected.
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Control the use of color in output
+    #[arg(long, value_enum, default_value = "auto", global = true)]
+    color: ColorChoice,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+#[clap(rename_all = "kebab_case")]
+enum ColorChoice {
+    /// Use color when writing to a terminal that supports it
+    Auto,
+    /// Always use color
+    Always,
+    /// Never use color
+    Never,
+}
+
+impl ColorChoice {
+    /// Finds the `--color` choice in the raw arguments
+    ///
+    /// clap prints help and errors while parsing, so the choice has to be known up front
+    /// for that output to honor it too.
+    fn from_args() -> Self {
+        let mut args = std::env::args_os().skip(1);
+        let mut choice = ColorChoice::Auto;
+        while let Some(arg) = args.next() {
+            let value = match arg.to_str() {
+                Some("--") => break,
+                Some("--color") => args.next(),
+                Some(arg) => arg.strip_prefix("--color=").map(Into::into),
+                None => None,
+            };
+            if let Some(value) = value.as_ref().and_then(|v| v.to_str()) {
+                if let Ok(value) = ColorChoice::from_str(value, false) {
+                    choice = value;
+                }
+            }
+        }
+        choice
+    }
+}
+
+impl From<ColorChoice> for clap::ColorChoice {
+    fn from(choice: ColorChoice) -> Self {
+        match choice {
+            ColorChoice::Auto => clap::ColorChoice::Auto,
+            ColorChoice::Always => clap::ColorChoice::Always,
+            ColorChoice::Never => clap::ColorChoice::Never,
+        }
+    }
+}
+
+impl From<ColorChoice> for anstream::ColorChoice {
+    fn from(choice: ColorChoice) -> Self {
+        match choice {
+            ColorChoice::Auto => anstream::ColorChoice::Auto,
+            ColorChoice::Always => anstream::ColorChoice::Always,
+            ColorChoice::Never => anstream::ColorChoice::Never,
+        }
+    }
 }
 
 #[derive(ValueEnum, Debug, Clone)]
@@ -38,51 +117,337 @@ enum VersionOutputFormat {
     Json,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+enum CheckFormat {
+    Text,
+    Json,
+    /// GitHub Actions annotations
+    Github,
+}
+
+impl From<CheckFormat> for commands::CheckFormat {
+    fn from(format: CheckFormat) -> Self {
+        match format {
+            CheckFormat::Text => commands::CheckFormat::Text,
+            CheckFormat::Json => commands::CheckFormat::Json,
+            CheckFormat::Github => commands::CheckFormat::Github,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+#[clap(rename_all = "kebab_case")]
+enum PythonPreference {
+    /// Prefer uv-managed Python installations over system ones
+    Managed,
+    /// Prefer system Python installations over uv-managed ones
+    System,
+    /// Only use uv-managed Python installations
+    OnlyManaged,
+    /// Only use system Python installations
+    OnlySystem,
+}
+
+impl PythonPreference {
+    /// The value of uv's `--python-preference` option
+    fn as_str(&self) -> &'static str {
+        match self {
+            PythonPreference::Managed => "managed",
+            PythonPreference::System => "system",
+            PythonPreference::OnlyManaged => "only-managed",
+            PythonPreference::OnlySystem => "only-system",
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+#[clap(rename_all = "kebab_case")]
+enum LogLevel {
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl LogLevel {
+    /// The name of the Python logging level
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warning => "WARN",
+            LogLevel::Error => "ERROR",
+            LogLevel::Critical => "CRITICAL",
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone)]
+#[clap(rename_all = "kebab_case")]
+enum ClearOutputsMode {
+    /// Remove all outputs
+    All,
+    /// Remove only images and other large binary outputs
+    Images,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+enum CellKind {
+    Code,
+    Markdown,
+    Raw,
+}
+
+#[derive(ValueEnum, Debug, Clone)]
+#[clap(rename_all = "kebab_case")]
+enum ExecExportFormat {
+    Html,
+    Script,
+    Markdown,
+    Notebook,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Preview the contents of a notebook
     Cat {
-        /// The file to display
-        file: std::path::PathBuf,
+        /// The files to display
+        ///
+        /// Multiple files are displayed in sequence, each preceded by a header with its path.
+        #[arg(required = true, env = "JUV_NOTEBOOK")]
+        files: Vec<std::path::PathBuf>,
         /// Display the file as python script
         #[arg(long, action)]
         script: bool,
         /// A pager to use for displaying the contents
-        #[arg(long, env = "JUV_PAGER")]
+        ///
+        /// Defaults to the `JUV_PAGER` environment variable (or the `pager` setting in
+        /// `juv.toml` or the global config) when not writing to a file.
+        #[arg(long)]
         pager: Option<String>,
+        /// Write the contents to a file instead of stdout
+        #[arg(short, long, conflicts_with = "pager")]
+        output: Option<std::path::PathBuf>,
+        /// Strip ANSI escape sequences (e.g., colors, progress bars) from the contents
+        ///
+        /// They are always stripped unless the contents go to a terminal (or a pager).
+        #[arg(long)]
+        plain: bool,
+        /// The number of blank lines between cells
+        #[arg(long, value_name = "LINES", default_value_t = 1)]
+        cell_spacing: usize,
+        /// Leave out cells that are empty or only contain whitespace
+        #[arg(long)]
+        skip_empty: bool,
+        /// Include the text of cell outputs (streams, results, and errors)
+        #[arg(long)]
+        outputs: bool,
+        /// Truncate each cell's outputs to this many lines
+        #[arg(long, value_name = "N", requires = "outputs")]
+        max_output_lines: Option<usize>,
+        /// Annotate each code cell with its recorded execution count (e.g., `[3]`)
+        ///
+        /// Cells that haven't been executed are left as is.
+        #[arg(long)]
+        execution_count: bool,
+        /// Wrap source lines longer than this many columns
+        ///
+        /// Wrapping is for reading only: wrapped code is split across lines, so the output
+        /// (including `--script`) is no longer runnable.
+        #[arg(long, value_name = "COLUMNS", value_parser = clap::value_parser!(u64).range(1..))]
+        wrap: Option<u64>,
+        /// Display only cells of the given kinds (e.g., `--only markdown` for just the prose)
+        ///
+        /// Multiple kinds can be separated by commas (e.g., `--only code,raw`).
+        #[arg(long, value_enum, value_delimiter = ',', value_name = "KIND")]
+        only: Vec<CellKind>,
     },
     /// Initialize a new notebook
     Init {
         /// The name of the project
+        ///
+        /// Use `-` to write the notebook to stdout instead of a file.
         file: Option<std::path::PathBuf>,
         /// The interpreter version specifier
         #[arg(short, long)]
         python: Option<String>,
+        /// Whether to prefer uv-managed or system Python installations
+        #[arg(long, value_enum, env = "JUV_PYTHON_PREFERENCE")]
+        python_preference: Option<PythonPreference>,
+        /// The name of the kernelspec to record in the notebook (e.g., a conda env's kernel)
+        ///
+        /// Frontends otherwise pick their default kernel, usually `python3`.
+        #[arg(long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+        kernel: Option<String>,
+        /// Overwrite the notebook if it already exists
+        #[arg(long)]
+        force: bool,
+        /// Ignore `.ipynb_checkpoints/` in the enclosing git repository, if any
+        #[arg(long)]
+        git: bool,
     },
     /// Launch a notebook or script in a Jupyter front end
     Run {
         /// The notebook to run
+        #[arg(env = "JUV_NOTEBOOK")]
         path: std::path::PathBuf,
         /// The runtime to use for running the notebook
+        ///
+        /// One of `lab`, `notebook`, or `nbclassic`, optionally with a version (e.g.,
+        /// `lab@4.2` or `notebook==6`). `@latest` is the same as omitting the version.
+        /// Other names refer to the `[runtimes]` registered in `juv.toml`.
+        ///
+        /// Defaults to the runtime saved with `--remember-runtime`, or `lab`.
         #[arg(long, env = "JUV_JUPYTER")]
         jupyter: Option<String>,
+        /// Save the runtime in the notebook's metadata, for when `--jupyter` isn't given
+        #[arg(long)]
+        remember_runtime: bool,
         /// Run with the additional packages installed
         #[arg(long)]
         with: Vec<String>,
+        /// Run with the packages listed in the given requirements file installed
+        ///
+        /// These are installed alongside the notebook's inline dependencies.
+        #[arg(long, value_name = "FILE")]
+        with_requirements: Vec<std::path::PathBuf>,
         /// The Python interpreter to use for the run environment.
         #[arg(short, long)]
         python: Option<String>,
+        /// Whether to prefer uv-managed or system Python installations
+        #[arg(long, value_enum, env = "JUV_PYTHON_PREFERENCE")]
+        python_preference: Option<PythonPreference>,
         /// Run in juv managed mode
         #[arg(long, action)]
         managed: bool,
+        /// Check that the runtime can be installed before launching it
+        ///
+        /// Resolves the runtime (e.g., `lab@4.2`) with `uv pip compile` first, so an impossible
+        /// version is reported right away. This adds a resolution to every launch.
+        #[arg(long)]
+        check_runtime: bool,
         /// Don't actually start the Jupyter runtime.
         ///
         /// Prints the command that would be run and the generated "run" script.
         #[arg(long, action)]
         dry_run: bool,
+        /// Start the Jupyter server in the background and return immediately.
+        ///
+        /// Prints the server's URL and PID. Server output is written to a log file.
+        /// Stop the server with `kill <PID>`.
+        #[arg(long, conflicts_with = "dry_run")]
+        detach: bool,
+        /// Highlight the server's URL by passing its output through juv
+        ///
+        /// juv then ignores Ctrl-C itself, so it can pass through the server's output while
+        /// the server shuts down.
+        #[arg(long, conflicts_with = "detach")]
+        capture_url: bool,
+        /// Skip merging the Jupyter data directories of the environments into a fresh one
+        ///
+        /// Speeds up launching when the set of extensions is stable, but Jupyter then only sees
+        /// the extensions installed in the notebook's own environment (or user-wide ones)
+        /// rather than those from every environment uv layers together.
+        #[arg(long)]
+        no_setup: bool,
+        /// Launch Jupyter with a script generated from this template instead of the built-in one
+        ///
+        /// The template is a Python script where `{meta}` and `{sys_argv}` are replaced with the
+        /// notebook's inline metadata and Jupyter's arguments, `{main_import}` with the module
+        /// providing Jupyter's `main`, and `{setup_script}` with juv's data directory setup.
+        /// Defaults to the `run-template` in `juv.toml` (or the global config). Only applies to
+        /// the Jupyter runtimes.
+        #[arg(long, value_name = "FILE")]
+        run_template: Option<std::path::PathBuf>,
+        /// Open this file (relative to the project root) instead of the notebook
+        ///
+        /// The server is rooted at the project (or workspace) containing the current directory
+        /// (or the directory given by `--project`, or the current directory with
+        /// `--no-project`).
+        /// The notebook's dependencies are still used for the environment.
+        #[arg(long, value_name = "PATH")]
+        open: Option<std::path::PathBuf>,
+        /// The port for the Jupyter server to listen on
+        #[arg(long)]
+        port: Option<u16>,
+        /// Pick the next free port if `--port` is already in use
+        #[arg(long, requires = "port")]
+        auto_port: bool,
+        /// The IP address for the Jupyter server to listen on
+        #[arg(long)]
+        ip: Option<String>,
+        /// The token used to authenticate with the Jupyter server
+        #[arg(long)]
+        token: Option<String>,
+        /// Don't open the Jupyter front end in a browser
+        #[arg(long)]
+        no_browser: bool,
+        /// Open the front end in a browser even when no display is detected
+        ///
+        /// Without a display (`DISPLAY`, `WAYLAND_DISPLAY`, or `BROWSER` on Linux), juv implies
+        /// `--no-browser` and the server's URL is printed instead.
+        #[arg(long, conflicts_with = "no_browser")]
+        browser: bool,
+        /// Don't warn about outputs with JavaScript in the notebook
+        ///
+        /// Frontends run these scripts when rendering the outputs, so juv warns about them in
+        /// case the notebook comes from an untrusted source (e.g., was downloaded).
+        #[arg(long)]
+        trust: bool,
+        /// Allow the Jupyter server to run as root
+        #[arg(long)]
+        allow_root: bool,
+        /// The log level of the Jupyter server
+        #[arg(long, value_enum)]
+        log_level: Option<LogLevel>,
         /// Additional arguments to pass to the Jupyter runtime
         #[arg(trailing_var_arg = true)]
         jupyter_args: Vec<String>,
+        /// Use the project (or workspace) in the given directory
+        #[arg(long, conflicts_with = "no_project")]
+        project: Option<std::path::PathBuf>,
+        /// Avoid discovering the project or workspace
+        #[arg(long)]
+        no_project: bool,
+        /// Set an environment variable for the Jupyter server (e.g., `--env KEY=VALUE`)
+        #[arg(long, value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Load environment variables from a dotenv-style file
+        #[arg(long)]
+        env_file: Option<std::path::PathBuf>,
+    },
+    /// Execute a notebook and open the results, rendered as HTML, in the browser
+    ///
+    /// Unlike `run`, no Jupyter server is started: the notebook is executed once, like
+    /// `exec --to html`, and the HTML is written to a temporary file.
+    Preview {
+        /// The notebook to preview
+        #[arg(env = "JUV_NOTEBOOK")]
+        path: std::path::PathBuf,
+        /// The Python interpreter to use for the exec environment
+        #[arg(short, long)]
+        python: Option<String>,
+        /// Whether to prefer uv-managed or system Python installations
+        #[arg(long, value_enum, env = "JUV_PYTHON_PREFERENCE")]
+        python_preference: Option<PythonPreference>,
+        /// Run with the additional packages installed
+        #[arg(long)]
+        with: Vec<String>,
+        /// Set an environment variable for the notebook (e.g., `--env KEY=VALUE`)
+        #[arg(long, value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Load environment variables from a dotenv-style file
+        #[arg(long)]
+        env_file: Option<std::path::PathBuf>,
+        /// Print the path of the HTML file instead of opening it
+        #[arg(long)]
+        no_open: bool,
+        /// Use the project (or workspace) in the given directory
+        #[arg(long, conflicts_with = "no_project")]
+        project: Option<std::path::PathBuf>,
         /// Avoid discovering the project or workspace
         #[arg(long)]
         no_project: bool,
@@ -90,17 +455,127 @@ enum Commands {
     /// Execute a notebook as a script
     Exec {
         /// The notebook to execute
+        #[arg(env = "JUV_NOTEBOOK")]
         path: std::path::PathBuf,
         /// The Python interpreter to use for the exec environment
         #[arg(short, long)]
         python: Option<String>,
+        /// Whether to prefer uv-managed or system Python installations
+        #[arg(long, value_enum, env = "JUV_PYTHON_PREFERENCE")]
+        python_preference: Option<PythonPreference>,
         /// Run with the additional packages installed
         #[arg(long)]
         with: Vec<String>,
+        /// Set an environment variable for the notebook (e.g., `--env KEY=VALUE`)
+        #[arg(long, value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Load environment variables from a dotenv-style file
+        #[arg(long)]
+        env_file: Option<std::path::PathBuf>,
+        /// Export the executed notebook (with outputs) to another format
+        ///
+        /// `script` and `markdown` are rendered like `juv cat --outputs` (with `--script`
+        /// for `script`), and `html` with nbconvert.
+        #[arg(long, value_enum)]
+        to: Option<ExecExportFormat>,
+        /// Where to write the exported notebook
+        ///
+        /// Defaults to the notebook's path with the extension of the `--to` format
+        /// (`<name>.executed.ipynb` for `notebook`).
+        #[arg(short, long, requires = "to")]
+        output: Option<std::path::PathBuf>,
+        /// Write the executed notebook into this directory instead of next to the notebook
+        ///
+        /// The notebook's path relative to the working directory is kept (e.g.,
+        /// `reports/a.ipynb` is written to `<DIR>/reports/a.ipynb`, or `<DIR>/reports/a.html`
+        /// with `--to html`), so the sources are never touched. Missing directories are
+        /// created. Notebooks outside the working directory can't be mirrored and are
+        /// rejected.
+        #[arg(
+            long,
+            value_name = "DIR",
+            conflicts_with_all = ["output", "capture_last", "connect"]
+        )]
+        output_dir: Option<std::path::PathBuf>,
+        /// Record execution details in the executed notebook's metadata
+        ///
+        /// Start and end times, duration, and the juv version are stored under
+        /// `metadata.papermill`, in the same shape papermill uses. Requires `--to notebook` (or
+        /// `--output-dir`); the source notebook is never modified.
+        #[arg(long)]
+        record_metadata: bool,
+        /// Abort if the notebook produces no output for this many seconds
+        ///
+        /// The timer resets whenever the notebook prints to stdout or stderr, so long
+        /// running cells that report progress are not interrupted.
+        #[arg(long, value_name = "SECONDS")]
+        no_output_timeout: Option<u64>,
+        /// Abort if any one cell runs for longer than this many seconds
+        ///
+        /// Requires running the notebook in a kernel (`--to`, `--output-dir`,
+        /// `--capture-last`, or `--connect`). With `--to`, the cell gets an error output in the export.
+        #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+        cell_timeout: Option<u64>,
+        /// Abort if the whole notebook runs for longer than this many seconds
+        ///
+        /// Setting up the environment counts against the limit. Exits with code 124, like
+        /// `timeout`, as does running out of `--cell-timeout`.
+        #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+        timeout: Option<u64>,
+        /// Print only the output of the last code cell to stdout
+        ///
+        /// Prints the cell's `text/plain` output, or the given mime type (e.g.,
+        /// `--capture-last=application/json`). Fails if the last cell has no such output.
+        #[arg(
+            long,
+            value_name = "MIME",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "text/plain",
+            value_parser = parse_mime_type,
+            conflicts_with = "to"
+        )]
+        capture_last: Option<String>,
+        /// Execute the notebook in an already-running kernel, given its connection file
+        ///
+        /// The cells run one by one in the kernel's own environment, keeping its state, and
+        /// their output is printed as it arrives. The notebook's inline dependencies are not
+        /// installed.
+        #[arg(long, value_name = "CONNECTION_FILE", conflicts_with_all = ["to", "capture_last"])]
+        connect: Option<std::path::PathBuf>,
+        /// Set a parameter for the notebook (e.g., `--param alpha 0.5`)
+        ///
+        /// Parameters are assigned in a cell injected after the cell tagged `parameters`,
+        /// like papermill's `-p`. Values are parsed as Python's `True`, `False`, `None`, or a
+        /// number when they look like one, and are strings otherwise.
+        #[arg(long = "param", value_names = ["KEY", "VALUE"], num_args = 2)]
+        param: Vec<String>,
+        /// Load parameters from a YAML or JSON file, like papermill's `-f`
+        ///
+        /// Parameters given with `--param` take precedence over the ones in the file.
+        #[arg(short = 'f', long, value_name = "FILE")]
+        params_file: Option<std::path::PathBuf>,
+        /// The kernel to execute the notebook with, when exporting or capturing its output
+        ///
+        /// Defaults to the kernel in the notebook's `kernelspec`, or `python3` (the kernel
+        /// `ipykernel` provides) when it has none. Useful when the recorded kernel doesn't
+        /// exist in the environment uv creates.
+        #[arg(long, value_name = "NAME", conflicts_with = "connect")]
+        kernel_name: Option<String>,
+        /// Use the project (or workspace) in the given directory
+        #[arg(long, conflicts_with = "no_project")]
+        project: Option<std::path::PathBuf>,
+        /// Avoid discovering the project or workspace
+        #[arg(long)]
+        no_project: bool,
     },
     /// Add dependencies to a notebook
     Add {
         /// The notebook to add dependencies to
+        ///
+        /// If the first argument isn't a notebook (`.ipynb`) and `JUV_NOTEBOOK` is set, all
+        /// arguments are treated as packages.
+        #[arg(env = "JUV_NOTEBOOK")]
         path: std::path::PathBuf,
         /// The packages to add
         packages: Vec<String>,
@@ -122,40 +597,255 @@ enum Commands {
         /// Commit to use when adding a dependency from Git
         #[arg(long)]
         editable: bool,
+        /// The kind of version specifier to use when adding dependencies
+        #[arg(long, value_parser = ["lower", "major", "minor", "exact"])]
+        bounds: Option<String>,
+        /// Update packages that are already dependencies to their latest version
+        ///
+        /// Without it, packages the notebook already depends on are skipped unless a
+        /// version is given.
+        #[arg(long)]
+        upgrade: bool,
+        /// Constrain versions using the given requirements file
+        #[arg(short, long)]
+        constraint: Vec<std::path::PathBuf>,
+        /// Override versions using the given requirements file
+        #[arg(long)]
+        r#override: Vec<std::path::PathBuf>,
+        /// Also lock the notebook's dependencies after adding
+        ///
+        /// Notebooks that already have a lock (in their `uv.lock` metadata) are always
+        /// relocked, so the lock doesn't go stale.
+        #[arg(long)]
+        update_lock: bool,
+        /// Fail instead of warning when a local requirement contains the notebook itself, or
+        /// when uv is too old
+        #[arg(long)]
+        strict: bool,
+        /// Show how the dependencies would change, without updating the notebook
+        #[arg(long)]
+        dry_run: bool,
+        /// Fail, leaving the notebook untouched, if the new dependencies would raise the
+        /// minimum Python in `requires-python`
+        #[arg(long)]
+        no_python_bump: bool,
+        /// Resolve the notebook's path from the project (or workspace) in the given directory
+        #[arg(long, conflicts_with = "no_project")]
+        project: Option<std::path::PathBuf>,
+        /// Only resolve the notebook's path from the working directory
+        #[arg(long)]
+        no_project: bool,
     },
     /// Clear notebook cell outputs
     ///
     /// Supports multiple files and glob patterns (e.g., *.ipynb, notebooks/*.ipynb)
     Clear {
         /// The files to clear, can be a glob pattern
+        #[arg(env = "JUV_NOTEBOOK")]
         files: Vec<String>,
         /// Check if the notebooks are cleared
         #[arg(long)]
         check: bool,
+        /// The format to report `--check` results in
+        ///
+        /// `json` prints an object with a `clean` boolean and the `dirty` notebook paths to stdout,
+        /// and `github` prints GitHub Actions annotations for the dirty notebooks to stdout.
+        #[arg(long, default_value = "text", value_enum, requires = "check")]
+        format: CheckFormat,
+        /// Only clear cell outputs, keeping execution counts
+        #[arg(long, conflicts_with = "counts_only")]
+        outputs_only: bool,
+        /// Only clear execution counts, keeping cell outputs
+        #[arg(long)]
+        counts_only: bool,
+        /// Which outputs to clear
+        #[arg(
+            long,
+            default_value = "all",
+            value_enum,
+            conflicts_with = "counts_only"
+        )]
+        outputs: ClearOutputsMode,
+        /// Only clear notebooks that changed since the given git revision (e.g., `HEAD`)
+        ///
+        /// Includes untracked notebooks. Outside of a git repository, all notebooks are cleared.
+        #[arg(long, value_name = "REV")]
+        since: Option<String>,
+        /// Also set the notebooks' `nbformat_minor` version (4.5 if no version is given)
+        ///
+        /// Notebooks with cells can't be set below 4.5, since their cell ids require it, and
+        /// asking for that is an error.
+        #[arg(
+            long,
+            value_name = "MINOR",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "5",
+            value_parser = clap::value_parser!(i32).range(0..=5)
+        )]
+        nbformat_minor: Option<i32>,
+    },
+    /// Format notebooks into a canonical form
+    ///
+    /// Sorts keys, splits cell sources into lines, and assigns missing or duplicate cell ids.
+    /// Outputs are kept as is.
+    Fmt {
+        /// The files to format, can be a glob pattern
+        #[arg(env = "JUV_NOTEBOOK")]
+        files: Vec<String>,
+        /// Check if the notebooks are formatted, without writing them
+        #[arg(long)]
+        check: bool,
+        /// The format to report `--check` results in
+        ///
+        /// `json` prints an object with a `clean` boolean and the `dirty` notebook paths to stdout,
+        /// and `github` prints GitHub Actions annotations for the dirty notebooks to stdout.
+        #[arg(long, default_value = "text", value_enum, requires = "check")]
+        format: CheckFormat,
+        /// Also set the notebooks' `nbformat_minor` version (4.5 if no version is given)
+        ///
+        /// Notebooks with cells can't be set below 4.5, since their cell ids require it, and
+        /// asking for that is an error.
+        #[arg(
+            long,
+            value_name = "MINOR",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "5",
+            value_parser = clap::value_parser!(i32).range(0..=5)
+        )]
+        nbformat_minor: Option<i32>,
+    },
+    /// Inspect the notebook's inline script metadata (PEP 723)
+    Meta {
+        #[command(subcommand)]
+        command: MetaCommands,
     },
     /// Display juv's version
     Version {
         #[arg(long, default_value = "text", value_enum)]
         output_format: VersionOutputFormat,
     },
+    /// Generate shell completion scripts
+    ///
+    /// e.g., `juv completions zsh > _juv`
+    Completions {
+        /// The shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+    /// Generate man pages
+    Manpage {
+        /// Write a man page for juv and each subcommand into this directory
+        ///
+        /// If omitted, the top-level man page is written to stdout.
+        #[arg(long)]
+        output_dir: Option<std::path::PathBuf>,
+    },
     /// Quick edit a notebook as markdown
     Edit {
         /// The file to edit
         file: std::path::PathBuf,
         /// The editor to use
+        ///
+        /// Defaults to the `EDITOR` environment variable, or `VISUAL` when that's unset.
         #[arg(short, long, env = "EDITOR")]
         editor: Option<String>,
+        /// Pass the editor its flag to wait for the file to be closed
+        ///
+        /// This is done automatically for known GUI editors (e.g., `code` and `subl`).
+        #[arg(long, overrides_with = "no_wait")]
+        wait: bool,
+        /// Don't pass the editor a flag to wait for the file to be closed
+        #[arg(long, overrides_with = "wait")]
+        no_wait: bool,
+        /// Only edit the cell at this (0-based) index
+        #[arg(long)]
+        cell: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MetaCommands {
+    /// Print the inline script metadata as TOML
+    Show {
+        /// The notebook to read
+        #[arg(env = "JUV_NOTEBOOK")]
+        path: std::path::PathBuf,
+        /// Print the parsed fields as JSON instead
+        #[arg(long)]
+        json: bool,
     },
+    /// Edit the inline script metadata as TOML in your editor
+    ///
+    /// The edited TOML must be valid, otherwise the notebook is left untouched.
+    Edit {
+        /// The notebook to edit
+        #[arg(env = "JUV_NOTEBOOK")]
+        path: std::path::PathBuf,
+        /// The editor to use
+        ///
+        /// Defaults to the `EDITOR` environment variable, or `VISUAL` when that's unset.
+        #[arg(short, long, env = "EDITOR")]
+        editor: Option<String>,
+        /// Pass the editor its flag to wait for the file to be closed
+        ///
+        /// This is done automatically for known GUI editors (e.g., `code` and `subl`).
+        #[arg(long, overrides_with = "no_wait")]
+        wait: bool,
+        /// Don't pass the editor a flag to wait for the file to be closed
+        #[arg(long, overrides_with = "wait")]
+        no_wait: bool,
+    },
+}
+
+/// Falls back to the `VISUAL` environment variable when no editor is given
+fn editor_or_visual(editor: Option<String>) -> Option<String> {
+    editor
+        .filter(|editor| !editor.trim().is_empty())
+        .or_else(|| std::env::var("VISUAL").ok())
+}
+
+/// Combines `--wait` and `--no-wait`, where neither means to decide based on the editor
+fn wait_override(wait: bool, no_wait: bool) -> Option<bool> {
+    match (wait, no_wait) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let matches = Cli::command()
+        .color(ColorChoice::from_args().into())
+        .get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    // Status and error messages are written through anstream, which strips colors as needed
+    anstream::ColorChoice::write_global(cli.color.into());
     let printer = match (cli.verbose, cli.quiet) {
         (true, false) => printer::Printer::Verbose,
         (false, true) => printer::Printer::Quiet,
         _ => printer::Printer::Default,
     };
-    match Cli::parse().command {
+    if let Some(directory) = &cli.directory {
+        if let Err(err) = std::env::set_current_dir(directory) {
+            anyhow::bail!("Failed to change to `{}`: {}", directory.display(), err);
+        }
+    }
+    match cli.command {
+        Commands::Meta { command } => match command {
+            MetaCommands::Show { path, json } => commands::meta_show(&path, json),
+            MetaCommands::Edit {
+                path,
+                editor,
+                wait,
+                no_wait,
+            } => commands::meta_edit(
+                &printer,
+                &path,
+                editor_or_visual(editor).as_deref(),
+                wait_override(wait, no_wait),
+            ),
+        },
         Commands::Version { output_format } => {
             match output_format {
                 VersionOutputFormat::Text => {
@@ -169,62 +859,404 @@ fn main() -> Result<()> {
             std::io::stdout().write_all(b"\n")?;
             Ok(())
         }
-        Commands::Init { file, python } => {
-            commands::init(&printer, file.as_deref(), python.as_deref())
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "juv", &mut std::io::stdout());
+            Ok(())
         }
-        Commands::Cat {
+        Commands::Manpage { output_dir } => match output_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(&dir)?;
+                clap_mangen::generate_to(Cli::command(), &dir)?;
+                writeln!(
+                    printer.stderr(),
+                    "Generated man pages in `{}`",
+                    dir.display().cyan()
+                )?;
+                Ok(())
+            }
+            None => {
+                clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?;
+                Ok(())
+            }
+        },
+        Commands::Init {
             file,
+            python,
+            python_preference,
+            kernel,
+            force,
+            git,
+        } => commands::init(
+            &printer,
+            file.as_deref(),
+            python.as_deref(),
+            python_preference.map(|p| p.as_str()),
+            kernel.as_deref(),
+            force,
+            git,
+            cli.offline,
+        ),
+        Commands::Cat {
+            files,
             script,
             pager,
-        } => commands::cat(&printer, &file, script, pager.as_deref()),
-        Commands::Clear { files, check } => commands::clear(&printer, &files, check),
-        Commands::Edit { file, editor } => commands::edit(&printer, &file, editor.as_deref()),
+            output,
+            plain,
+            cell_spacing,
+            skip_empty,
+            outputs,
+            max_output_lines,
+            execution_count,
+            wrap,
+            only,
+        } => {
+            let pager = match output {
+                Some(_) => None,
+                None => match pager.or_else(|| std::env::var("JUV_PAGER").ok()) {
+                    Some(pager) => Some(pager),
+                    None => config::Config::discover(&std::env::current_dir()?)?.pager,
+                },
+            };
+            commands::cat(
+                &printer,
+                &files,
+                script,
+                pager.as_deref(),
+                output.as_deref(),
+                plain,
+                commands::RenderOptions {
+                    blank_lines: cell_spacing,
+                    skip_empty,
+                    outputs,
+                    max_output_lines,
+                    execution_counts: execution_count,
+                    wrap: wrap.map(|columns| columns as usize),
+                    kinds: if only.is_empty() {
+                        commands::CellKinds::ALL
+                    } else {
+                        commands::CellKinds {
+                            code: only.contains(&CellKind::Code),
+                            markdown: only.contains(&CellKind::Markdown),
+                            raw: only.contains(&CellKind::Raw),
+                        }
+                    },
+                },
+            )
+        }
+        Commands::Clear {
+            files,
+            check,
+            format,
+            outputs_only,
+            counts_only,
+            outputs,
+            since,
+            nbformat_minor,
+        } => {
+            let outputs = match (counts_only, outputs) {
+                (true, _) => notebook::ClearOutputs::Keep,
+                (false, ClearOutputsMode::All) => notebook::ClearOutputs::All,
+                (false, ClearOutputsMode::Images) => notebook::ClearOutputs::Images,
+            };
+            let policy = notebook::ClearPolicy {
+                outputs,
+                execution_count: !outputs_only,
+            };
+            commands::clear(
+                &printer,
+                &files,
+                check,
+                format.into(),
+                policy,
+                since.as_deref(),
+                nbformat_minor,
+            )
+        }
+        Commands::Fmt {
+            files,
+            check,
+            format,
+            nbformat_minor,
+        } => commands::fmt(&printer, &files, check, format.into(), nbformat_minor),
+        Commands::Edit {
+            file,
+            editor,
+            wait,
+            no_wait,
+            cell,
+        } => {
+            let editor = editor_or_visual(editor);
+            let wait = wait_override(wait, no_wait);
+            commands::edit(&printer, &file, editor.as_deref(), wait, cell)
+        }
         Commands::Add {
-            path,
-            packages,
+            mut path,
+            mut packages,
             requirements,
             extra,
             tag,
             branch,
             rev,
             editable,
-        } => commands::add(
-            &printer,
-            &path,
-            &packages,
-            requirements.as_deref(),
-            &extra,
-            tag.as_deref(),
-            branch.as_deref(),
-            rev.as_deref(),
-            editable,
-        ),
+            bounds,
+            upgrade,
+            constraint,
+            r#override,
+            update_lock,
+            strict,
+            dry_run,
+            no_python_bump,
+            project,
+            no_project,
+        } => {
+            // `juv add numpy` with `JUV_NOTEBOOK` set parses `numpy` as the notebook
+            if !notebook::is_notebook_path(&path) {
+                if let Some(notebook) = std::env::var_os("JUV_NOTEBOOK") {
+                    packages.insert(0, path.to_string_lossy().into_owned());
+                    path = notebook.into();
+                }
+            }
+            commands::add(
+                &printer,
+                &path,
+                commands::AddOptions {
+                    packages: &packages,
+                    requirements: requirements.as_deref(),
+                    extras: &extra,
+                    tag: tag.as_deref(),
+                    branch: branch.as_deref(),
+                    rev: rev.as_deref(),
+                    editable,
+                    bounds: bounds.as_deref(),
+                    upgrade,
+                    constraints: &constraint,
+                    overrides: &r#override,
+                    update_lock,
+                    strict,
+                    dry_run,
+                    no_python_bump,
+                    project: commands::Project::new(project.as_deref(), no_project)?,
+                    offline: cli.offline,
+                },
+            )
+        }
         Commands::Run {
             path,
             jupyter,
+            remember_runtime,
             with,
+            with_requirements,
             python,
+            python_preference,
             jupyter_args,
+            port,
+            auto_port,
+            ip,
+            token,
+            no_browser,
+            browser,
+            trust,
+            allow_root,
+            log_level,
             managed,
+            check_runtime,
             dry_run,
+            detach,
+            capture_url,
+            no_setup,
+            run_template,
+            open,
+            project,
             no_project,
+            env,
+            env_file,
         } => commands::run(
             &printer,
             &path,
-            &with,
-            python.as_deref(),
-            jupyter.as_deref(),
-            &jupyter_args,
+            commands::RunOptions {
+                with: &with,
+                with_requirements: &with_requirements,
+                python: python.as_deref(),
+                python_preference: python_preference.map(|p| p.as_str()),
+                jupyter: jupyter.as_deref(),
+                jupyter_args: &jupyter_args,
+                server_options: script::ServerOptions {
+                    port,
+                    ip,
+                    token,
+                    no_browser,
+                    allow_root,
+                    log_level: log_level.map(|level| level.as_str()),
+                },
+                auto_port,
+                browser,
+                trust,
+                env: &env,
+                env_file: env_file.as_deref(),
+                project: commands::Project::new(project.as_deref(), no_project)?,
+                managed,
+                check_runtime,
+                dry_run,
+                detach,
+                capture_url,
+                setup: !no_setup,
+                run_template: run_template.as_deref(),
+                open: open.as_deref(),
+                quiet: cli.quiet,
+                offline: cli.offline,
+                remember_runtime,
+            },
+        ),
+        Commands::Exec {
+            path,
+            python,
+            python_preference,
+            with,
+            env,
+            env_file,
+            to,
+            output,
+            output_dir,
+            record_metadata,
+            no_output_timeout,
+            cell_timeout,
+            timeout,
+            capture_last,
+            connect,
+            param,
+            params_file,
+            kernel_name,
+            project,
             no_project,
-            managed,
-            dry_run,
+        } => commands::exec(
+            &printer,
+            &path,
+            commands::ExecOptions {
+                python: python.as_deref(),
+                python_preference: python_preference.map(|p| p.as_str()),
+                with: &with,
+                env: &env,
+                env_file: env_file.as_deref(),
+                export: to.map(|to| match to {
+                    ExecExportFormat::Html => script::ExportFormat::Html,
+                    ExecExportFormat::Script => script::ExportFormat::Script,
+                    ExecExportFormat::Markdown => script::ExportFormat::Markdown,
+                    ExecExportFormat::Notebook => script::ExportFormat::Notebook,
+                }),
+                output: output.as_deref(),
+                output_dir: output_dir.as_deref(),
+                record_metadata,
+                no_output_timeout: no_output_timeout.map(std::time::Duration::from_secs),
+                cell_timeout: cell_timeout.map(std::time::Duration::from_secs),
+                timeout: timeout.map(std::time::Duration::from_secs),
+                capture_last: capture_last.as_deref(),
+                connect: connect.as_deref(),
+                params_file: params_file.as_deref(),
+                params: &param
+                    .chunks_exact(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect::<Vec<_>>(),
+                kernel_name: kernel_name.as_deref(),
+                project: commands::Project::new(project.as_deref(), no_project)?,
+                quiet: cli.quiet,
+                offline: cli.offline,
+            },
+        ),
+        Commands::Preview {
+            path,
+            python,
+            python_preference,
+            with,
+            env,
+            env_file,
+            no_open,
+            project,
+            no_project,
+        } => commands::preview(
+            &printer,
+            &path,
+            commands::ExecOptions {
+                python: python.as_deref(),
+                python_preference: python_preference.map(|p| p.as_str()),
+                with: &with,
+                env: &env,
+                env_file: env_file.as_deref(),
+                project: commands::Project::new(project.as_deref(), no_project)?,
+                quiet: cli.quiet,
+                offline: cli.offline,
+                ..Default::default()
+            },
+            no_open,
         ),
-        Commands::Exec { path, python, with } => {
-            commands::exec(&printer, &path, python.as_deref(), &with, cli.quiet)
-        }
+    }
+}
+
+/// Parses a `type/subtype` mime type (e.g., `application/vnd.plotly.v1+json`)
+fn parse_mime_type(s: &str) -> Result<String, String> {
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+    match s.split_once('/') {
+        Some((kind, subtype)) if is_token(kind) && is_token(subtype) => Ok(s.to_string()),
+        _ => Err(format!(
+            "expected a mime type like `text/plain`, found `{s}`"
+        )),
+    }
+}
+
+/// Parses a `KEY=VALUE` environment variable assignment
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected `KEY=VALUE`, found `{s}`")),
     }
 }
 
 fn version() -> &'static str {
     env!("CARGO_PKG_VERSION")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mime_types() {
+        for mime in [
+            "text/plain",
+            "application/vnd.plotly.v1+json",
+            "image/svg+xml",
+        ] {
+            assert_eq!(parse_mime_type(mime).as_deref(), Ok(mime));
+        }
+        for mime in [
+            "text",
+            "text/",
+            "/plain",
+            "text/plain\"",
+            "text/pl ain",
+            "a/b/c",
+        ] {
+            assert!(parse_mime_type(mime).is_err(), "{mime}");
+        }
+    }
+
+    #[test]
+    fn completions() {
+        Cli::command().debug_assert();
+        let cli = Cli::try_parse_from(["juv", "completions", "fish"]).unwrap();
+        let Commands::Completions { shell } = cli.command else {
+            panic!("expected the completions command");
+        };
+        let mut script = vec![];
+        clap_complete::generate(shell, &mut Cli::command(), "juv", &mut script);
+        let script = String::from_utf8(script).unwrap();
+        for subcommand in ["exec", "clear", "cat"] {
+            assert!(script.contains(subcommand), "{subcommand}");
+        }
+        assert!(Cli::try_parse_from(["juv", "completions", "tcsh"]).is_err());
+    }
+}
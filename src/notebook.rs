@@ -1,9 +1,21 @@
 use anyhow::Result;
-use nbformat::v4::{Cell, CellId, CellMetadata, JupyterCellMetadata, Metadata};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use nbformat::v4::{Cell, CellId, CellMetadata, JupyterCellMetadata, KernelSpec, Metadata, Output};
+use serde::de::{DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::Path;
 
 pub struct Notebook(nbformat::v4::Notebook);
 
+/// The tag of the cell holding a notebook's default parameters (as used by papermill)
+const PARAMETERS_TAG: &str = "parameters";
+
+/// The tag of the cell holding the parameters injected for an execution
+const INJECTED_PARAMETERS_TAG: &str = "injected-parameters";
+
 impl AsRef<nbformat::v4::Notebook> for Notebook {
     fn as_ref(&self) -> &nbformat::v4::Notebook {
         &self.0
@@ -17,51 +29,598 @@ impl AsMut<nbformat::v4::Notebook> for Notebook {
 }
 
 impl Notebook {
+    /// Reads a notebook from disk.
+    ///
+    /// Cell sources are always normalized to a list of lines, where every line but the
+    /// last ends with `\n`, regardless of how they were stored in the file.
     pub fn from_path(path: &Path) -> Result<Self> {
-        let json = std::fs::read_to_string(path)?;
-        Ok(Self(match nbformat::parse_notebook(&json)? {
+        Self::parse(&Self::read_json(path)?)
+    }
+
+    /// Reads a notebook while skipping over cell outputs.
+    ///
+    /// Outputs (e.g., base64 encoded images) often make up the bulk of a notebook, so
+    /// commands that don't need them can avoid reading them into memory. The resulting
+    /// notebook is identical to [`Notebook::from_path`] except that it has no outputs.
+    pub fn from_path_without_outputs(path: &Path) -> Result<Self> {
+        // serde_json reads byte by byte, so buffer on this side of the `dyn Read`
+        let reader = BufReader::new(open_reader(path)?);
+        let NotebookWithoutOutputs(value) = serde_json::from_reader(reader)?;
+        Self::parse(&serde_json::to_string(&value)?)
+    }
+
+    fn parse(json: &str) -> Result<Self> {
+        let nb = match nbformat::parse_notebook(json) {
+            Ok(nb) => nb,
+            Err(err) => {
+                // nbformat >= 4.5 requires every cell to have a unique `id`, but some tools
+                // still write notebooks without them. Similarly, `source` may be stored as a
+                // single string rather than a list of lines. Repair both and try again.
+                let mut value: serde_json::Value = serde_json::from_str(json)?;
+                if let Some(major) = value.get("nbformat").and_then(|v| v.as_i64()) {
+                    let minor = value.get("nbformat_minor").and_then(|v| v.as_i64());
+                    ensure_supported_version(major, minor.unwrap_or(0))?;
+                }
+                let repaired =
+                    Self::ensure_cell_ids(&mut value) | Self::normalize_sources(&mut value);
+                if !repaired {
+                    return Err(err.into());
+                }
+                nbformat::parse_notebook(&serde_json::to_string(&value)?)?
+            }
+        };
+        Ok(Self(match nb {
             nbformat::Notebook::V4(nb) => nb,
             nbformat::Notebook::Legacy(legacy_nb) => nbformat::upgrade_legacy_notebook(legacy_nb)?,
         }))
     }
 
-    // Whether the notebook outputs are cleared
-    pub fn is_cleared(&self) -> bool {
-        for cell in &self.as_ref().cells {
+    /// Writes the notebook to disk in its canonical form (see [`Notebook::to_formatted_json`]),
+    /// compressing it if the path ends in `.gz`
+    ///
+    /// Every command writes notebooks this way, so `juv fmt` has nothing left to change.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        write_json(path, &self.to_formatted_json()?)
+    }
+
+    /// Reads the raw JSON of a notebook from disk, decompressing it if needed
+    pub fn read_json(path: &Path) -> Result<String> {
+        let mut json = String::new();
+        open_reader(path)?.read_to_string(&mut json)?;
+        Ok(json)
+    }
+
+    /// Serializes the notebook in its canonical form.
+    ///
+    /// Keys are sorted (like Jupyter does) so the output doesn't depend on the order they
+    /// were read in, and the file ends with a newline.
+    pub fn to_formatted_json(&self) -> Result<String> {
+        // Without `preserve_order`, `serde_json::Value` objects are sorted maps
+        let value = serde_json::to_value(&self.0)?;
+        Ok(serde_json::to_string_pretty(&value)? + "\n")
+    }
+
+    /// Normalizes the cells: sources are split into lines with `\n` endings, and every cell
+    /// gets a unique id.
+    pub fn normalize(&mut self) {
+        let mut seen = std::collections::HashSet::new();
+        for cell in self.0.cells.iter_mut() {
+            let (Cell::Code { id, source, .. }
+            | Cell::Markdown { id, source, .. }
+            | Cell::Raw { id, source, .. }) = cell;
+            *source = split_source(&join_source(source));
+            if !seen.insert(id.as_str().to_string()) {
+                *id = new_cell_id();
+                seen.insert(id.as_str().to_string());
+            }
+        }
+    }
+
+    /// Splits any cell `source` stored as a single string into a list of lines.
+    ///
+    /// Returns whether any cell was updated.
+    fn normalize_sources(value: &mut serde_json::Value) -> bool {
+        let Some(cells) = value.get_mut("cells").and_then(|c| c.as_array_mut()) else {
+            return false;
+        };
+        let mut changed = false;
+        for source in cells.iter_mut().filter_map(|c| c.get_mut("source")) {
+            if let Some(text) = source.as_str() {
+                *source = split_source(text).into();
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Assigns a new id to any cell that lacks one (or duplicates an earlier cell's id).
+    ///
+    /// Returns whether any cell was updated.
+    fn ensure_cell_ids(value: &mut serde_json::Value) -> bool {
+        let Some(cells) = value.get_mut("cells").and_then(|c| c.as_array_mut()) else {
+            return false;
+        };
+        let mut seen = std::collections::HashSet::new();
+        let mut changed = false;
+        for cell in cells.iter_mut().filter_map(|c| c.as_object_mut()) {
+            let id = cell
+                .get("id")
+                .and_then(|id| id.as_str())
+                .map(str::to_string);
+            if id.is_some_and(|id| seen.insert(id)) {
+                continue;
+            }
+            let id = new_cell_id();
+            seen.insert(id.as_str().to_string());
+            cell.insert("id".into(), id.as_str().into());
+            changed = true;
+        }
+        changed
+    }
+
+    /// Reads the `nbformat_minor` version a notebook is stored with
+    ///
+    /// Notebooks older than 4.5 are upgraded when read, so this can differ from the one a
+    /// [`Notebook`] read from the same file ends up with.
+    pub fn read_nbformat_minor(path: &Path) -> Result<i32> {
+        let version: NotebookVersion = serde_json::from_reader(open_reader(path)?)?;
+        Ok(version.nbformat_minor.try_into()?)
+    }
+
+    /// Whether every cell of a notebook is stored with a unique id
+    ///
+    /// Cells without one get a new, random id each time the notebook is read, so they can't
+    /// be matched by id between reads.
+    pub fn has_stored_cell_ids(path: &Path) -> Result<bool> {
+        let stored: StoredCellIds = serde_json::from_reader(BufReader::new(open_reader(path)?))?;
+        let mut seen = std::collections::HashSet::new();
+        Ok(stored
+            .cells
+            .into_iter()
+            .all(|cell| cell.id.is_some_and(|id| seen.insert(id))))
+    }
+
+    /// The oldest `nbformat_minor` that can represent the notebook
+    ///
+    /// Cell ids, which juv always writes, were added in nbformat 4.5.
+    pub fn min_nbformat_minor(&self) -> i32 {
+        if self.cells().is_empty() {
+            0
+        } else {
+            5
+        }
+    }
+
+    /// Sets the notebook's `nbformat_minor` version
+    pub fn set_nbformat_minor(&mut self, minor: i32) {
+        self.0.nbformat_minor = minor;
+    }
+
+    /// The notebook's cells, in order
+    pub fn cells(&self) -> &[Cell] {
+        &self.0.cells
+    }
+
+    /// Mutable access to the notebook's cells, in order
+    pub fn cells_mut(&mut self) -> &mut [Cell] {
+        &mut self.0.cells
+    }
+
+    /// Adds a code cell assigning parameters, like papermill does
+    ///
+    /// The cell goes after the cell tagged `parameters` (so it overrides the defaults there),
+    /// or first if there is none, replacing any cell injected by a previous run. Returns
+    /// whether a `parameters` cell was found.
+    pub fn inject_parameters(&mut self, source: &str) -> bool {
+        let has_tag = |cell: &Cell, tag: &str| {
+            cell.metadata()
+                .tags
+                .as_ref()
+                .is_some_and(|tags| tags.iter().any(|t| t == tag))
+        };
+        self.0
+            .cells
+            .retain(|cell| !has_tag(cell, INJECTED_PARAMETERS_TAG));
+        let position = self
+            .0
+            .cells
+            .iter()
+            .position(|cell| has_tag(cell, PARAMETERS_TAG));
+        let mut cell = new_code_cell(source, None);
+        if let Cell::Code { metadata, .. } = &mut cell {
+            metadata.tags = Some(vec![INJECTED_PARAMETERS_TAG.to_string()]);
+        }
+        self.insert_cell(position.map_or(0, |i| i + 1), cell)
+            .expect("the index is at most the number of cells");
+        position.is_some()
+    }
+
+    /// Inserts a cell at `index`, shifting the cells after it
+    ///
+    /// `index` may be the number of cells, to add the cell at the end.
+    pub fn insert_cell(&mut self, index: usize, cell: Cell) -> Result<()> {
+        if index > self.0.cells.len() {
+            return Err(self.out_of_range(index));
+        }
+        self.0.cells.insert(index, cell);
+        Ok(())
+    }
+
+    /// Removes and returns the cell at `index`, shifting the cells after it
+    pub fn remove_cell(&mut self, index: usize) -> Result<Cell> {
+        if index >= self.0.cells.len() {
+            return Err(self.out_of_range(index));
+        }
+        Ok(self.0.cells.remove(index))
+    }
+
+    /// Replaces the cell at `index`, returning the previous one
+    pub fn replace_cell(&mut self, index: usize, cell: Cell) -> Result<Cell> {
+        match self.0.cells.get_mut(index) {
+            Some(existing) => Ok(std::mem::replace(existing, cell)),
+            None => Err(self.out_of_range(index)),
+        }
+    }
+
+    fn out_of_range(&self, index: usize) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Cell index {} is out of range for a notebook with {} cells",
+            index,
+            self.0.cells.len()
+        )
+    }
+
+    /// The notebook's code cells, in order
+    pub fn code_cells(&self) -> impl Iterator<Item = &Cell> {
+        self.cells()
+            .iter()
+            .filter(|cell| matches!(cell, Cell::Code { .. }))
+    }
+
+    /// The notebook's markdown cells, in order
+    pub fn markdown_cells(&self) -> impl Iterator<Item = &Cell> {
+        self.cells()
+            .iter()
+            .filter(|cell| matches!(cell, Cell::Markdown { .. }))
+    }
+
+    // Whether the notebook outputs are cleared according to the given policy
+    pub fn is_cleared(&self, policy: ClearPolicy) -> bool {
+        for cell in self.cells() {
             if let Cell::Code {
                 execution_count,
                 outputs,
                 ..
             } = cell
             {
-                if execution_count.is_some() || !outputs.is_empty() {
+                if policy.execution_count && execution_count.is_some() {
+                    return false;
+                }
+                let outputs_cleared = match policy.outputs {
+                    ClearOutputs::Keep => true,
+                    ClearOutputs::All => outputs.is_empty(),
+                    ClearOutputs::Images => !outputs.iter().any(has_binary_media),
+                };
+                if !outputs_cleared {
                     return false;
                 }
             }
         }
-        true
+        policy.outputs != ClearOutputs::All || !self.0.metadata.additional.contains_key("widgets")
     }
 
-    pub fn clear_cells(&mut self) -> Result<()> {
-        for cell in &mut self.0.cells {
+    pub fn clear_cells(&mut self, policy: ClearPolicy) -> Result<()> {
+        for cell in self.cells_mut() {
             if let Cell::Code {
                 execution_count,
                 outputs,
                 ..
             } = cell
             {
-                *execution_count = None;
-                outputs.clear();
+                if policy.execution_count {
+                    *execution_count = None;
+                }
+                match policy.outputs {
+                    ClearOutputs::Keep => {}
+                    ClearOutputs::All => outputs.clear(),
+                    ClearOutputs::Images => {
+                        let mut stripped = Vec::with_capacity(outputs.len());
+                        for output in outputs.iter() {
+                            stripped.extend(strip_binary_media(output)?);
+                        }
+                        *outputs = stripped;
+                    }
+                }
             }
         }
+        if policy.outputs == ClearOutputs::All {
+            // Saved widget state belongs to the widget outputs that were just removed
+            self.0.metadata.additional.remove("widgets");
+        }
         Ok(())
     }
 }
 
+/// Which parts of the code cells to reset when clearing a notebook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClearPolicy {
+    pub outputs: ClearOutputs,
+    pub execution_count: bool,
+}
+
+impl Default for ClearPolicy {
+    fn default() -> Self {
+        Self {
+            outputs: ClearOutputs::All,
+            execution_count: true,
+        }
+    }
+}
+
+/// Which cell outputs to remove when clearing a notebook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearOutputs {
+    /// Leave outputs untouched
+    Keep,
+    /// Remove all outputs
+    All,
+    /// Remove only images and other large binary mime bundles
+    Images,
+}
+
+/// Mime types that carry large (typically base64 encoded) binary payloads
+fn is_binary_mime_type(mime: &str) -> bool {
+    mime.starts_with("image/")
+        || mime.starts_with("audio/")
+        || mime.starts_with("video/")
+        || mime == "application/pdf"
+}
+
+fn has_binary_media(output: &Output) -> bool {
+    serde_json::to_value(output)
+        .ok()
+        .as_ref()
+        .and_then(|value| value.get("data"))
+        .and_then(|data| data.as_object())
+        .is_some_and(|data| data.keys().any(|mime| is_binary_mime_type(mime)))
+}
+
+/// Removes binary mime bundles from an output's data, returning `None` if nothing is left to display
+fn strip_binary_media(output: &Output) -> Result<Option<Output>> {
+    if !has_binary_media(output) {
+        return Ok(Some(output.clone()));
+    }
+    let mut value = serde_json::to_value(output)?;
+    for key in ["data", "metadata"] {
+        if let Some(bundle) = value.get_mut(key).and_then(|v| v.as_object_mut()) {
+            bundle.retain(|mime, _| !is_binary_mime_type(mime));
+        }
+    }
+    if value["data"]
+        .as_object()
+        .is_some_and(|data| data.is_empty())
+    {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_value(value)?))
+}
+
+/// The JSON of a notebook, with the cell outputs emptied while reading.
+///
+/// The outputs are skipped by the deserializer without being buffered.
+struct NotebookWithoutOutputs(serde_json::Value);
+
+impl<'de> Deserialize<'de> for NotebookWithoutOutputs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let notebook = deserializer.deserialize_map(ObjectWithout {
+            key: "cells",
+            seed: CellsWithoutOutputs,
+        })?;
+        Ok(Self(notebook.into()))
+    }
+}
+
+/// Deserializes a JSON object, using `seed` for the value of `key`
+struct ObjectWithout<S> {
+    key: &'static str,
+    seed: S,
+}
+
+impl<'de, S> Visitor<'de> for ObjectWithout<S>
+where
+    S: DeserializeSeed<'de, Value = serde_json::Value> + Copy,
+{
+    type Value = serde_json::Map<String, serde_json::Value>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut object = serde_json::Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let value = if key == self.key {
+                map.next_value_seed(self.seed)?
+            } else {
+                map.next_value()?
+            };
+            object.insert(key, value);
+        }
+        Ok(object)
+    }
+}
+
+/// Deserializes a notebook's cells, replacing their outputs with an empty list
+#[derive(Clone, Copy)]
+struct CellsWithoutOutputs;
+
+impl<'de> DeserializeSeed<'de> for CellsWithoutOutputs {
+    type Value = serde_json::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for CellsWithoutOutputs {
+    type Value = serde_json::Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a list of cells")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut cells = vec![];
+        while let Some(cell) = seq.next_element_seed(CellWithoutOutputs)? {
+            cells.push(cell);
+        }
+        Ok(cells.into())
+    }
+}
+
+/// Deserializes a single cell, replacing its outputs with an empty list
+#[derive(Clone, Copy)]
+struct CellWithoutOutputs;
+
+impl<'de> DeserializeSeed<'de> for CellWithoutOutputs {
+    type Value = serde_json::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let cell = deserializer.deserialize_map(ObjectWithout {
+            key: "outputs",
+            seed: NoOutputs,
+        })?;
+        Ok(cell.into())
+    }
+}
+
+/// Skips over a cell's outputs, deserializing to an empty list
+#[derive(Clone, Copy)]
+struct NoOutputs;
+
+impl<'de> DeserializeSeed<'de> for NoOutputs {
+    type Value = serde_json::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        IgnoredAny::deserialize(deserializer)?;
+        Ok(serde_json::Value::Array(vec![]))
+    }
+}
+
+#[derive(Deserialize)]
+struct NotebookVersion {
+    #[serde(default)]
+    nbformat_minor: i64,
+}
+
+#[derive(Deserialize)]
+struct StoredCellIds {
+    cells: Vec<StoredCellId>,
+}
+
+#[derive(Deserialize)]
+struct StoredCellId {
+    #[serde(default)]
+    id: Option<String>,
+}
+
+/// The newest major nbformat version juv can read
+const MAX_NBFORMAT: i64 = 4;
+
+/// Fails with a clear error for notebooks saved in an nbformat newer than juv supports.
+fn ensure_supported_version(major: i64, minor: i64) -> Result<()> {
+    if major > MAX_NBFORMAT {
+        anyhow::bail!(
+            "This notebook uses nbformat {}.{}, which juv doesn't support yet (the newest supported version is {}.x)",
+            major,
+            minor,
+            MAX_NBFORMAT
+        );
+    }
+    Ok(())
+}
+
+/// Whether the path refers to a notebook, either plain (`.ipynb`) or compressed (`.ipynb.gz`)
+pub fn is_notebook_path(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.ends_with(".ipynb") || name.ends_with(".ipynb.gz")
+}
+
+fn is_gzipped(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz")
+}
+
+/// Opens a notebook for reading, transparently decompressing gzipped files.
+///
+/// Compression is detected from either the `.gz` extension or the gzip magic bytes.
+fn open_reader(path: &Path) -> Result<Box<dyn Read>> {
+    let mut file = BufReader::new(std::fs::File::open(path)?);
+    if is_gzipped(path) || file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        let mut reader = BufReader::new(GzDecoder::new(file));
+        skip_bom(&mut reader)?;
+        Ok(Box::new(reader))
+    } else {
+        skip_bom(&mut file)?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Skips a UTF-8 byte order mark, which some (mostly Windows) tools write before the JSON
+/// and which JSON parsers reject
+fn skip_bom(reader: &mut impl BufRead) -> std::io::Result<()> {
+    const BOM: &[u8] = b"\xEF\xBB\xBF";
+    if reader.fill_buf()?.starts_with(BOM) {
+        reader.consume(BOM.len());
+    }
+    Ok(())
+}
+
+/// Writes JSON to disk, compressing it if the path ends in `.gz`
+fn write_json(path: &Path, json: &str) -> Result<()> {
+    if is_gzipped(path) {
+        let mut encoder = GzEncoder::new(std::fs::File::create(path)?, Compression::default());
+        encoder.write_all(json.as_bytes())?;
+        encoder.finish()?;
+    } else {
+        std::fs::write(path, json)?;
+    }
+    Ok(())
+}
+
+/// Splits text into the lines of a cell source, normalizing `\r\n` line endings to `\n`.
+///
+/// Lone `\r` characters are left untouched since they may be intentional.
+pub fn split_source(text: &str) -> Vec<String> {
+    text.split_inclusive('\n')
+        .map(|line| match line.strip_suffix("\r\n") {
+            Some(line) => format!("{line}\n"),
+            None => line.to_string(),
+        })
+        .collect()
+}
+
+/// Joins the lines of a cell source, normalizing `\r\n` line endings to `\n`.
+pub fn join_source(lines: &[String]) -> String {
+    lines.concat().replace("\r\n", "\n")
+}
+
+/// Generates a short, random cell id
+fn new_cell_id() -> CellId {
+    let uuid = uuid::Uuid::new_v4().to_string();
+    // ok to unwrap because we know the first part of the uuid is valid
+    CellId::try_from(uuid.split('-').next().unwrap()).unwrap()
+}
+
 pub struct NotebookBuilder {
     nb: nbformat::v4::Notebook,
 }
 
+impl Default for NotebookBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NotebookBuilder {
     pub fn new() -> Self {
         Self {
@@ -80,35 +639,7 @@ impl NotebookBuilder {
     }
 
     fn _code_cell(mut self, source: &str, hidden: Option<bool>) -> Self {
-        let uuid = uuid::Uuid::new_v4().to_string();
-        // TODO: Could have our own builder for this as well
-        let cell = Cell::Code {
-            // ok to unwrap because we know the first part of the uuid is valid
-            id: CellId::try_from(uuid.split('-').next().unwrap()).unwrap(),
-            metadata: CellMetadata {
-                id: None,
-                collapsed: None,
-                scrolled: None,
-                deletable: None,
-                editable: None,
-                format: None,
-                jupyter: hidden.map(|h| JupyterCellMetadata {
-                    source_hidden: Some(h),
-                    outputs_hidden: None,
-                }),
-                name: None,
-                tags: None,
-                execution: None,
-            },
-            execution_count: None,
-            source: source
-                .trim()
-                .split_inclusive('\n')
-                .map(|s| s.to_string())
-                .collect(),
-            outputs: vec![],
-        };
-        self.nb.cells.push(cell);
+        self.nb.cells.push(new_code_cell(source, hidden));
         self
     }
 
@@ -120,7 +651,256 @@ impl NotebookBuilder {
         self._code_cell(source, None)
     }
 
+    /// Points the notebook at the named kernelspec (e.g., a kernel installed for a conda env)
+    pub fn kernelspec(mut self, name: &str) -> Self {
+        self.nb.metadata.kernelspec = Some(KernelSpec {
+            display_name: name.to_string(),
+            name: name.to_string(),
+            language: Some("python".to_string()),
+            additional: Default::default(),
+        });
+        self
+    }
+
     pub fn build(self) -> Notebook {
         Notebook(self.nb)
     }
 }
+
+fn new_code_cell(source: &str, hidden: Option<bool>) -> Cell {
+    // TODO: Could have our own builder for this as well
+    Cell::Code {
+        id: new_cell_id(),
+        metadata: CellMetadata {
+            id: None,
+            collapsed: None,
+            scrolled: None,
+            deletable: None,
+            editable: None,
+            format: None,
+            jupyter: hidden.map(|h| JupyterCellMetadata {
+                source_hidden: Some(h),
+                outputs_hidden: None,
+            }),
+            name: None,
+            tags: None,
+            execution: None,
+        },
+        execution_count: None,
+        source: split_source(source.trim()),
+        outputs: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[test]
+    fn insert_cell_bounds() {
+        let mut nb = NotebookBuilder::new().code_cell("a").code_cell("b").build();
+        nb.insert_cell(0, new_code_cell("first", None)).unwrap();
+        nb.insert_cell(3, new_code_cell("last", None)).unwrap();
+        nb.insert_cell(2, new_code_cell("middle", None)).unwrap();
+        let sources: Vec<_> = nb
+            .cells()
+            .iter()
+            .map(|cell| cell.source().concat())
+            .collect();
+        assert_eq!(sources, ["first", "a", "middle", "b", "last"]);
+
+        let err = nb.insert_cell(6, new_code_cell("past", None)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cell index 6 is out of range for a notebook with 5 cells"
+        );
+        assert_eq!(nb.cells().len(), 5);
+
+        let mut empty = NotebookBuilder::new().build();
+        assert!(empty.insert_cell(1, new_code_cell("a", None)).is_err());
+        empty.insert_cell(0, new_code_cell("a", None)).unwrap();
+        assert_eq!(empty.cells().len(), 1);
+    }
+
+    #[test]
+    fn remove_and_replace_cell_bounds() {
+        let sources = |nb: &Notebook| {
+            nb.cells()
+                .iter()
+                .map(|cell| cell.source().concat())
+                .collect::<Vec<_>>()
+        };
+        let mut nb = NotebookBuilder::new()
+            .code_cell("a")
+            .code_cell("b")
+            .code_cell("c")
+            .build();
+        let old = nb.replace_cell(0, new_code_cell("first", None)).unwrap();
+        assert_eq!(old.source().concat(), "a");
+        let old = nb.replace_cell(2, new_code_cell("last", None)).unwrap();
+        assert_eq!(old.source().concat(), "c");
+        let err = nb.replace_cell(3, new_code_cell("past", None)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cell index 3 is out of range for a notebook with 3 cells"
+        );
+        assert_eq!(sources(&nb), ["first", "b", "last"]);
+
+        let err = nb.remove_cell(3).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cell index 3 is out of range for a notebook with 3 cells"
+        );
+        assert_eq!(nb.remove_cell(2).unwrap().source().concat(), "last");
+        assert_eq!(nb.remove_cell(0).unwrap().source().concat(), "first");
+        assert_eq!(sources(&nb), ["b"]);
+
+        let mut empty = NotebookBuilder::new().build();
+        assert!(empty.remove_cell(0).is_err());
+        assert!(empty.replace_cell(0, new_code_cell("a", None)).is_err());
+        assert!(empty.cells().is_empty());
+    }
+
+    #[test]
+    fn is_cleared_follows_the_policy() {
+        let path = fixture("executed.ipynb");
+        let mut nb = Notebook::from_path(&path).unwrap();
+        assert!(!nb.is_cleared(ClearPolicy::default()));
+        let images = ClearPolicy {
+            outputs: ClearOutputs::Images,
+            execution_count: false,
+        };
+        assert!(!nb.is_cleared(images));
+        nb.clear_cells(images).unwrap();
+        assert!(nb.is_cleared(images));
+        assert!(!nb.is_cleared(ClearPolicy::default()));
+        nb.clear_cells(ClearPolicy::default()).unwrap();
+        assert!(nb.is_cleared(ClearPolicy::default()));
+    }
+
+    #[test]
+    fn reading_without_outputs_matches_clearing_them() {
+        let path = fixture("executed.ipynb");
+        let mut expected = Notebook::from_path(&path).unwrap();
+        expected
+            .clear_cells(ClearPolicy {
+                outputs: ClearOutputs::All,
+                execution_count: false,
+            })
+            .unwrap();
+        let lean = Notebook::from_path_without_outputs(&path).unwrap();
+        assert_eq!(
+            lean.to_formatted_json().unwrap(),
+            expected.to_formatted_json().unwrap()
+        );
+    }
+
+    #[test]
+    fn reading_without_outputs_repairs_legacy_notebooks() {
+        let path = fixture("legacy.ipynb");
+        let full = Notebook::from_path(&path).unwrap();
+        let lean = Notebook::from_path_without_outputs(&path).unwrap();
+        assert_eq!(lean.as_ref().nbformat_minor, full.as_ref().nbformat_minor);
+        assert_eq!(lean.cells().len(), 3);
+        for (lean, full) in lean.cells().iter().zip(full.cells()) {
+            assert_eq!(lean.source(), full.source());
+            if let Cell::Code { outputs, .. } = lean {
+                assert!(outputs.is_empty());
+            }
+        }
+        assert_eq!(
+            lean.cells()[0].source(),
+            ["# Legacy\n", "A notebook saved before cells had ids."]
+        );
+    }
+
+    #[test]
+    fn missing_and_duplicate_cell_ids_are_assigned() {
+        let nb = Notebook::from_path(&fixture("missing-ids.ipynb")).unwrap();
+        let cells = &nb.as_ref().cells;
+        let ids: Vec<_> = cells.iter().map(|cell| cell.id().as_str()).collect();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0], "setup");
+        assert!(ids[1] != "setup" && ids[2] != "setup" && ids[1] != ids[2]);
+        let sources: Vec<_> = cells.iter().map(|cell| cell.source().concat()).collect();
+        assert_eq!(sources, ["x = 1", "# Duplicated id", "y = 2"]);
+    }
+
+    #[test]
+    fn string_sources_are_split_into_lines() {
+        let nb = Notebook::from_path(&fixture("string-sources.ipynb")).unwrap();
+        let cells = &nb.as_ref().cells;
+        let sources: Vec<_> = cells.iter().map(Cell::source).collect();
+        assert_eq!(sources[0], ["# Title\n", "Some text\n"]);
+        assert_eq!(sources[1], ["x = 1\n", "print(x)"]);
+        assert!(sources[2].is_empty());
+        let ids: Vec<_> = cells.iter().map(|cell| cell.id().as_str()).collect();
+        assert_eq!(ids, ["intro", "code", "empty"]);
+    }
+
+    #[test]
+    fn crlf_line_endings_are_normalized() {
+        assert_eq!(split_source("a = 1\r\nb = 2\r\n"), ["a = 1\n", "b = 2\n"]);
+        assert_eq!(split_source("a\r\nb"), ["a\n", "b"]);
+        // A lone carriage return may be intentional (e.g., a progress bar)
+        assert_eq!(split_source("10%\r20%\n"), ["10%\r20%\n"]);
+        assert!(split_source("").is_empty());
+
+        let lines = ["# /// script\r\n".to_string(), "# ///".to_string()];
+        assert_eq!(join_source(&lines), "# /// script\n# ///");
+        assert_eq!(join_source(&split_source("x\r\ny")), "x\ny");
+    }
+
+    #[test]
+    fn newer_nbformat_versions_are_reported() {
+        let path = fixture("future.ipynb");
+        for result in [
+            Notebook::from_path(&path),
+            Notebook::from_path_without_outputs(&path),
+        ] {
+            let err = result.err().unwrap();
+            assert_eq!(
+                err.to_string(),
+                "This notebook uses nbformat 5.1, which juv doesn't support yet (the newest supported version is 4.x)"
+            );
+        }
+    }
+
+    #[test]
+    fn byte_order_marks_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut contents = b"\xEF\xBB\xBF".to_vec();
+        contents.extend(std::fs::read(fixture("missing-ids.ipynb")).unwrap());
+        let plain = dir.path().join("bom.ipynb");
+        std::fs::write(&plain, &contents).unwrap();
+        let gzipped = dir.path().join("bom.ipynb.gz");
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&contents).unwrap();
+        std::fs::write(&gzipped, encoder.finish().unwrap()).unwrap();
+
+        for path in [plain, gzipped] {
+            let nb = Notebook::from_path(&path).unwrap();
+            assert_eq!(nb.cells().len(), 3);
+            let lean = Notebook::from_path_without_outputs(&path).unwrap();
+            assert_eq!(lean.cells().len(), 3);
+            nb.write(&path).unwrap();
+            // Read past `open_reader`, which would skip a byte order mark itself
+            let mut written = std::fs::read(&path).unwrap();
+            if is_gzipped(&path) {
+                let mut decoded = vec![];
+                GzDecoder::new(written.as_slice())
+                    .read_to_end(&mut decoded)
+                    .unwrap();
+                written = decoded;
+            }
+            assert!(written.starts_with(b"{"), "{}", path.display());
+        }
+    }
+}
@@ -1,10 +1,18 @@
-use std::{borrow::Cow, path::Path, str::FromStr};
+use crate::config::CustomRuntime;
+use std::{
+    borrow::Cow,
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
 #[derive(Debug, PartialEq)]
 enum RuntimeKind {
     Notebook,
     Lab,
     Nbclassic,
+    /// A frontend registered in `juv.toml`
+    Custom(CustomRuntime),
 }
 
 #[derive(Debug, PartialEq)]
@@ -29,6 +37,13 @@ impl FromStr for Runtime {
             (s, None)
         };
 
+        // `latest` and `stable` are aliases for no pin, which lets uv pick the newest release
+        let version = match version.as_deref() {
+            None | Some("latest" | "stable") => None,
+            Some(v) if is_version_like(v) => version,
+            Some(v) => anyhow::bail!("Invalid version `{}` in runtime specifier: {}", v, s),
+        };
+
         let kind = match kind_str {
             "notebook" => RuntimeKind::Notebook,
             "lab" => RuntimeKind::Lab,
@@ -40,25 +55,87 @@ impl FromStr for Runtime {
     }
 }
 
+/// Checks that a runtime version looks like a (possibly partial) PEP 440 version
+fn is_version_like(version: &str) -> bool {
+    version.starts_with(|c: char| c.is_ascii_digit())
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".*+!-_".contains(c))
+}
+
+/// Common Jupyter server options exposed as first-class `juv run` flags
+///
+/// Each option is translated to the config trait of the runtime's server application:
+///
+/// | flag           | lab / notebook (v7) / nbclassic | notebook (v6)                   |
+/// | -------------- | ------------------------------- | ------------------------------- |
+/// | `--port`       | `--ServerApp.port`              | `--NotebookApp.port`            |
+/// | `--ip`         | `--ServerApp.ip`                | `--NotebookApp.ip`              |
+/// | `--token`      | `--IdentityProvider.token`      | `--NotebookApp.token`           |
+/// | `--no-browser` | `--ServerApp.open_browser`      | `--NotebookApp.open_browser`    |
+/// | `--allow-root` | `--ServerApp.allow_root`        | `--NotebookApp.allow_root`      |
+/// | `--log-level`  | `--ServerApp.log_level`         | `--NotebookApp.log_level`       |
+#[derive(Debug, Default)]
+pub struct ServerOptions {
+    pub port: Option<u16>,
+    pub ip: Option<String>,
+    pub token: Option<String>,
+    pub no_browser: bool,
+    pub allow_root: bool,
+    pub log_level: Option<&'static str>,
+}
+
 impl Runtime {
+    /// Creates a runtime that launches a frontend registered in `juv.toml`
+    pub fn custom(runtime: CustomRuntime) -> Self {
+        Runtime {
+            kind: RuntimeKind::Custom(runtime),
+            version: None,
+        }
+    }
+
+    /// Whether the runtime is a Jupyter server (as opposed to a custom frontend)
+    pub fn is_jupyter(&self) -> bool {
+        !matches!(self.kind, RuntimeKind::Custom(_))
+    }
+
     /// Provides the executable name for the runtime
-    fn exacutable(&self) -> &'static str {
-        match self.kind {
+    fn executable(&self) -> &str {
+        match &self.kind {
             RuntimeKind::Notebook => "jupyter-notebook",
             RuntimeKind::Lab => "jupyter-lab",
             RuntimeKind::Nbclassic => "jupyter-nbclassic",
+            RuntimeKind::Custom(runtime) => &runtime.command,
         }
     }
 
+    /// Whether the runtime is the classic notebook server (v6), which predates `jupyter_server`
+    fn is_notebook_v6(&self) -> bool {
+        if self.kind != RuntimeKind::Notebook {
+            return false;
+        }
+        // A pin like `6.*` isn't a version itself, but its release is all that matters
+        self.version
+            .as_deref()
+            .and_then(|version| {
+                version
+                    .trim_end_matches(".*")
+                    .parse::<pep440_rs::Version>()
+                    .ok()
+            })
+            .is_some_and(|version| version.release().first() == Some(&6))
+    }
+
     /// Provides the module specifer to import the main function for the runtime
     fn main_import(&self) -> &'static str {
-        if self.kind == RuntimeKind::Notebook && self.version.as_deref() == Some("6") {
+        if self.is_notebook_v6() {
             return "notebook.notebookapp";
         };
         match self.kind {
             RuntimeKind::Notebook => "notebook.app",
             RuntimeKind::Lab => "jupyterlab.labapp",
             RuntimeKind::Nbclassic => "nbclassic.notebookapp",
+            RuntimeKind::Custom(_) => unreachable!("custom runtimes are launched as executables"),
         }
     }
 
@@ -68,24 +145,94 @@ impl Runtime {
             RuntimeKind::Notebook => "notebook",
             RuntimeKind::Lab => "jupyterlab",
             RuntimeKind::Nbclassic => "nbclassic",
+            RuntimeKind::Custom(_) => unreachable!("custom runtimes aren't a single package"),
         }
     }
 
-    /// Provides the with args for the Runtime for uv --with=...
-    pub fn with_args(&self) -> Cow<'static, str> {
-        let specifier = if let Some(version) = &self.version {
-            Cow::Owned(format!("{}=={}", self.package_name(), version))
-        } else {
-            Cow::Borrowed(self.package_name())
+    /// Provides the name of the application class that configures the server
+    fn server_app(&self) -> &'static str {
+        if self.is_notebook_v6() {
+            return "NotebookApp";
+        }
+        "ServerApp"
+    }
+
+    /// Provides the config trait that configures a server option for the runtime
+    ///
+    /// Options are named after the `ServerApp` traits; this accounts for the ones that
+    /// are spelled differently (or live on another class) for the runtime's server.
+    fn server_trait(&self, option: &str) -> String {
+        match (self.server_app(), option) {
+            ("ServerApp", "token") => "IdentityProvider.token".to_string(),
+            ("NotebookApp", "root_dir") => "NotebookApp.notebook_dir".to_string(),
+            (app, option) => format!("{app}.{option}"),
+        }
+    }
+
+    /// Translates the server options into command line arguments for the runtime
+    ///
+    /// Custom runtimes aren't Jupyter servers, so the options don't apply to them.
+    pub fn server_args(&self, options: &ServerOptions) -> Vec<String> {
+        if !self.is_jupyter() {
+            return vec![];
+        }
+        let arg = |option: &str, value: &dyn std::fmt::Display| {
+            format!("--{}={}", self.server_trait(option), value)
+        };
+        let mut args = vec![];
+        if let Some(port) = options.port {
+            args.push(arg("port", &port));
+        }
+        if let Some(ip) = &options.ip {
+            args.push(arg("ip", ip));
+        }
+        if let Some(token) = &options.token {
+            args.push(arg("token", token));
+        }
+        if options.no_browser {
+            args.push(arg("open_browser", &"False"));
+        }
+        if options.allow_root {
+            args.push(arg("allow_root", &"True"));
+        }
+        if let Some(log_level) = options.log_level {
+            args.push(arg("log_level", &log_level));
+        }
+        args
+    }
+
+    /// Provides the argument that sets the directory the server is rooted at
+    pub fn root_dir_arg(&self, dir: &Path) -> String {
+        format!("--{}={}", self.server_trait("root_dir"), dir.display())
+    }
+
+    /// Provides the requirements to install the runtime with, one per entry
+    ///
+    /// Empty for a custom runtime that doesn't need any packages.
+    pub fn requirements(&self) -> Vec<String> {
+        if let RuntimeKind::Custom(runtime) = &self.kind {
+            return runtime.with.clone();
+        }
+        let specifier = match &self.version {
+            Some(version) => format!("{}=={}", self.package_name(), version),
+            None => self.package_name().to_string(),
         };
-        if self.kind == RuntimeKind::Notebook && self.version.as_deref() == Some("6") {
+        if self.is_notebook_v6() {
             // notebook v6 requires setuptools
-            format!("{},setuptools", specifier).into()
+            vec![specifier, "setuptools".to_string()]
         } else {
-            specifier
+            vec![specifier]
         }
     }
 
+    /// Provides the with args for the Runtime for uv --with=...
+    ///
+    /// Returns `None` for a custom runtime that doesn't need any packages.
+    pub fn with_args(&self) -> Option<String> {
+        let requirements = self.requirements();
+        (!requirements.is_empty()).then(|| requirements.join(","))
+    }
+
     /// Dynamically generates a script for uv to run the notebook/lab/nbclassic in an isolated environment
     #[allow(clippy::format_in_format_args)]
     pub fn prepare_run_script(
@@ -93,10 +240,19 @@ impl Runtime {
         path: &Path,
         meta: Option<&str>,
         is_managed: bool,
+        setup: bool,
         jupyter_args: &[String],
+        template: Option<&str>,
     ) -> String {
         let notebook = path.to_string_lossy();
-        let mut args: Vec<&str> = vec![self.exacutable(), notebook.as_ref()];
+        if let RuntimeKind::Custom(runtime) = &self.kind {
+            let mut args: Vec<&str> = vec![&runtime.command];
+            args.extend(runtime.args.iter().map(String::as_str));
+            args.push(notebook.as_ref());
+            args.extend(jupyter_args.iter().map(String::as_str));
+            return prepare_custom_run_script(meta, &args);
+        }
+        let mut args: Vec<&str> = vec![self.executable(), notebook.as_ref()];
         args.extend(jupyter_args.iter().map(String::as_str));
 
         let print_version: Cow<'static, str> = if is_managed {
@@ -110,6 +266,30 @@ impl Runtime {
             "".into()
         };
 
+        // Without the setup, Jupyter uses its default data directories
+        let (setup_script, call_setup) = if setup {
+            // Merged data directories are cached per juv version, in case the merge changes
+            let call_setup = format!("setup({:?})", env!("CARGO_PKG_VERSION"));
+            (include_str!("static/setup.py"), call_setup)
+        } else {
+            ("", String::new())
+        };
+
+        if let Some(template) = template {
+            // The template decides where the setup happens, so the call comes along with it
+            let setup_script = if setup {
+                format!("{setup_script}\n{call_setup}\n")
+            } else {
+                String::new()
+            };
+            // The metadata goes in last, so nothing in it is mistaken for a placeholder
+            return template
+                .replace("{main_import}", self.main_import())
+                .replace("{sys_argv}", &format!("{:?}", args))
+                .replace("{setup_script}", &setup_script)
+                .replace("{meta}", meta.unwrap_or(""));
+        }
+
         format!(
             r#"{meta}
 
@@ -119,7 +299,7 @@ def run():
     import sys
     from {main_import} import main
 
-    setup()
+    {call_setup}
     {print_version}
     sys.argv = {sys_argv}
     main()
@@ -127,10 +307,553 @@ def run():
 if __name__ == "__main__":
     run()"#,
             meta = meta.unwrap_or(""),
-            setup_script = include_str!("static/setup.py"),
+            setup_script = setup_script,
+            call_setup = call_setup,
             main_import = self.main_import(),
             print_version = print_version,
             sys_argv = format!("{:?}", args)
         )
     }
 }
+
+/// The placeholders a run template must contain, without which the notebook's dependencies
+/// wouldn't be installed or the notebook wouldn't be opened
+const REQUIRED_TEMPLATE_PLACEHOLDERS: [&str; 2] = ["{meta}", "{sys_argv}"];
+
+/// Checks that a template for [`Runtime::prepare_run_script`] has the required placeholders
+///
+/// Templates are Python scripts with `{meta}` (the inline script metadata), `{sys_argv}` (the
+/// arguments to launch Jupyter with), and optionally `{main_import}` (the module providing
+/// Jupyter's `main`) and `{setup_script}` (juv's setup of the Jupyter data directories).
+pub fn validate_run_template(template: &str) -> anyhow::Result<()> {
+    for placeholder in REQUIRED_TEMPLATE_PLACEHOLDERS {
+        if !template.contains(placeholder) {
+            anyhow::bail!("Run template is missing the `{}` placeholder", placeholder);
+        }
+    }
+    Ok(())
+}
+
+/// Generates a script for uv to launch a custom runtime's executable in the notebook's environment
+fn prepare_custom_run_script(meta: Option<&str>, args: &[&str]) -> String {
+    format!(
+        r#"{meta}
+
+def run():
+    import subprocess
+    import sys
+
+    sys.exit(subprocess.call({args:?}))
+
+if __name__ == "__main__":
+    run()"#,
+        meta = meta.unwrap_or(""),
+    )
+}
+
+/// Formats an executed notebook can be exported to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Html,
+    Script,
+    Markdown,
+    /// The executed notebook itself
+    Notebook,
+}
+
+impl ExportFormat {
+    /// Provides the name of the nbconvert exporter for the format
+    ///
+    /// Returns `None` for formats juv writes from the executed notebook itself.
+    pub fn exporter(&self) -> Option<&'static str> {
+        match self {
+            ExportFormat::Html => Some("html"),
+            ExportFormat::Script | ExportFormat::Markdown | ExportFormat::Notebook => None,
+        }
+    }
+
+    /// Provides the file extension for the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Html => "html",
+            ExportFormat::Script => "py",
+            ExportFormat::Markdown => "md",
+            ExportFormat::Notebook => "ipynb",
+        }
+    }
+}
+
+/// Limits on how long a notebook executed with a kernel may run
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    /// The longest any one cell may run
+    pub cell: Option<Duration>,
+    /// The longest the whole notebook may run, and the time it must be done by
+    pub notebook: Option<(Duration, SystemTime)>,
+}
+
+impl Timeouts {
+    /// Python defining `timeout_for(cell)`, which provides the seconds a cell may still run
+    /// (or `None` for no limit), and `timeout_message(index)`, which explains a timeout
+    fn python(&self) -> String {
+        let seconds = |duration: Option<Duration>| {
+            duration.map_or("None".to_string(), |d| d.as_secs_f64().to_string())
+        };
+        let deadline = self.notebook.map(|(_, deadline)| {
+            deadline
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+        });
+        format!(
+            r#"cell_timeout = {cell_timeout}
+    timeout, deadline = {timeout}, {deadline}
+
+    def timeout_for(cell):
+        limits = [] if cell_timeout is None else [cell_timeout]
+        if deadline is not None:
+            limits.append(deadline - time.time())
+        # A limit of zero means no limit to nbclient
+        return max(min(limits), 0.001) if limits else None
+
+    def timeout_message(index):
+        if deadline is not None and time.time() >= deadline:
+            return f"The notebook ran longer than {{timeout:g}} seconds (in cell {{index}})"
+        return f"Cell {{index}} ran longer than {{cell_timeout:g}} seconds""#,
+            cell_timeout = seconds(self.cell),
+            timeout = seconds(self.notebook.map(|(timeout, _)| timeout)),
+            deadline = seconds(deadline),
+        )
+    }
+}
+
+/// The exit code of a notebook that ran out of time, like coreutils' `timeout`
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Python starting the `main()` of the scripts that execute a notebook: the imports they
+/// share, the timeout helpers, and the notebook at `path` (gzipped or not) read into `nb`
+fn main_prelude(path: &Path, timeouts: Timeouts) -> String {
+    format!(
+        r#"def main():
+    import gzip
+    import json
+    import sys
+    import time
+    from pathlib import Path
+
+    import nbformat
+
+    {timeouts}
+
+    path = Path({path:?})
+    open_ = gzip.open if path.suffix == ".gz" else open
+    with open_(path, "rt", encoding="utf-8") as f:
+        nb = nbformat.read(f, as_version=4)"#,
+        path = path.to_string_lossy(),
+        timeouts = timeouts.python(),
+    )
+}
+
+/// Python defining `execute()`, which runs `nb` in a new kernel with nbclient
+///
+/// A cell that runs out of time raises `CellTimeoutError`, with the cell last in `running`.
+/// With `echo_streams`, stream outputs are also written to `stream` (a Python expression
+/// that may look at the stream `msg`) as the kernel produces them.
+fn nbclient_python(kernel_name: &str, echo_streams: bool, stream: &str) -> String {
+    format!(
+        r#"from nbclient import NotebookClient
+    from nbclient.exceptions import CellTimeoutError
+
+    class Client(NotebookClient):
+        def output(self, outs, msg, display_id, cell_index):
+            if {echo_streams} and msg["msg_type"] == "stream":
+                stream = {stream}
+                stream.write(msg["content"]["text"])
+                stream.flush()
+            return super().output(outs, msg, display_id, cell_index)
+
+    running = []
+
+    def timeout_func(cell):
+        running.append(cell)
+        return timeout_for(cell)
+
+    def execute():
+        Client(
+            nb,
+            kernel_name={kernel_name:?},
+            resources={{"metadata": {{"path": str(path.parent)}}}},
+            timeout_func=timeout_func,
+        ).execute()"#,
+        echo_streams = if echo_streams { "True" } else { "False" },
+    )
+}
+
+/// Dynamically generates a script for uv to execute a notebook and export the result
+///
+/// Formats without an nbconvert exporter get the executed notebook written to `output`
+/// instead. Requires `nbconvert` (or just `nbclient` for those) and `ipykernel` in the
+/// environment (e.g., via `uv run --with`). With `echo_streams`, stream outputs are also
+/// printed as the kernel produces them. A cell that runs out of time gets an error output in
+/// the export, and the script exits with [`TIMEOUT_EXIT_CODE`].
+pub fn prepare_exec_script(
+    path: &Path,
+    meta: Option<&str>,
+    kernel_name: &str,
+    format: ExportFormat,
+    output: &Path,
+    echo_streams: bool,
+    timeouts: Timeouts,
+) -> String {
+    let output = output.to_string_lossy();
+    let export = match format.exporter() {
+        Some(exporter) => format!(
+            r#"from nbconvert import get_exporter
+
+    body, _ = get_exporter({exporter:?})().from_notebook_node(nb)
+    Path({output:?}).write_text(body, encoding="utf-8")"#
+        ),
+        None => format!("nbformat.write(nb, {output:?})"),
+    };
+    format!(
+        r#"{meta}
+
+{prelude}
+
+    {nbclient}
+
+    message = None
+    try:
+        execute()
+    except CellTimeoutError:
+        # Record the timeout where it happened, so the export shows which cell stalled
+        cell = running[-1]
+        message = timeout_message(nb.cells.index(cell) + 1)
+        cell.outputs.append(
+            nbformat.v4.new_output("error", ename="TimeoutError", evalue=message, traceback=[])
+        )
+
+    {export}
+
+    if message is not None:
+        print(f"error: {{message}}", file=sys.stderr)
+        sys.exit({timeout_exit_code})
+
+if __name__ == "__main__":
+    main()"#,
+        meta = meta.unwrap_or(""),
+        prelude = main_prelude(path, timeouts),
+        nbclient = nbclient_python(
+            kernel_name,
+            echo_streams,
+            r#"sys.stderr if msg["content"]["name"] == "stderr" else sys.stdout"#,
+        ),
+        export = export,
+        timeout_exit_code = TIMEOUT_EXIT_CODE,
+    )
+}
+
+/// Dynamically generates a script for uv to execute a notebook and print the last code
+/// cell's output of the given mime type to stdout
+///
+/// Requires `nbclient` and `ipykernel` in the environment (e.g., via `uv run --with`).
+/// With `echo_streams`, stream outputs are printed to stderr as the kernel produces them. The
+/// script exits with [`TIMEOUT_EXIT_CODE`] if a cell runs out of time.
+pub fn prepare_capture_script(
+    path: &Path,
+    meta: Option<&str>,
+    kernel_name: &str,
+    mime: &str,
+    echo_streams: bool,
+    timeouts: Timeouts,
+) -> String {
+    format!(
+        r#"{meta}
+
+{prelude}
+
+    {nbclient}
+
+    try:
+        execute()
+    except CellTimeoutError:
+        message = timeout_message(nb.cells.index(running[-1]) + 1)
+        print(f"error: {{message}}", file=sys.stderr)
+        sys.exit({timeout_exit_code})
+
+    mime = {mime:?}
+    code_cells = [cell for cell in nb.cells if cell.cell_type == "code"]
+    outputs = code_cells[-1].outputs if code_cells else []
+    for output in reversed(outputs):
+        data = output.get("data", {{}})
+        if mime in data:
+            value = data[mime]
+            print(value if isinstance(value, str) else json.dumps(value))
+            return
+
+    print(f"error: The last cell has no `{{mime}}` output", file=sys.stderr)
+    sys.exit(1)
+
+if __name__ == "__main__":
+    main()"#,
+        meta = meta.unwrap_or(""),
+        prelude = main_prelude(path, timeouts),
+        // stdout only gets the captured output
+        nbclient = nbclient_python(kernel_name, echo_streams, "sys.stderr"),
+        mime = mime,
+        timeout_exit_code = TIMEOUT_EXIT_CODE,
+    )
+}
+
+/// Dynamically generates a script for uv to execute a notebook's code cells in an
+/// already-running kernel
+///
+/// Requires `jupyter_client` and `nbformat` in the environment (e.g., via `uv run --with`).
+/// The client reads the kernel's connection file (its transport, ip, ports, and signing
+/// key), sends each code cell to the kernel as an `execute_request` on the shell channel, and
+/// prints the stream, result, and error messages the kernel publishes on the iopub channel
+/// as they arrive. Execution stops at the first cell that raises. Nothing from the notebook's
+/// inline metadata is installed, since the kernel already has its environment, and the
+/// kernel keeps any state the cells create. A cell that runs out of time stops execution
+/// with [`TIMEOUT_EXIT_CODE`], though the kernel itself carries on with it.
+pub fn prepare_connect_script(path: &Path, connection_file: &Path, timeouts: Timeouts) -> String {
+    format!(
+        r#"
+{prelude}
+
+    from jupyter_client import BlockingKernelClient
+
+    client = BlockingKernelClient(connection_file={connection_file:?})
+    client.load_connection_file()
+    client.start_channels()
+    try:
+        client.wait_for_ready(timeout=60)
+        for index, cell in enumerate(nb.cells, start=1):
+            if cell.cell_type != "code" or not cell.source.strip():
+                continue
+            try:
+                reply = client.execute_interactive(
+                    cell.source, store_history=True, timeout=timeout_for(cell)
+                )
+            except TimeoutError:
+                print(f"error: {{timeout_message(index)}}", file=sys.stderr)
+                sys.exit({timeout_exit_code})
+            if reply["content"]["status"] != "ok":
+                sys.exit(1)
+    finally:
+        client.stop_channels()
+
+if __name__ == "__main__":
+    main()"#,
+        prelude = main_prelude(path, timeouts),
+        connection_file = connection_file.to_string_lossy(),
+        timeout_exit_code = TIMEOUT_EXIT_CODE,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_matrix() {
+        // (specifier, main import, server app, uv --with)
+        let cases = [
+            ("lab", "jupyterlab.labapp", "ServerApp", "jupyterlab"),
+            (
+                "lab@4.2",
+                "jupyterlab.labapp",
+                "ServerApp",
+                "jupyterlab==4.2",
+            ),
+            ("notebook", "notebook.app", "ServerApp", "notebook"),
+            ("notebook@latest", "notebook.app", "ServerApp", "notebook"),
+            ("notebook@7", "notebook.app", "ServerApp", "notebook==7"),
+            (
+                "notebook==7.2.1",
+                "notebook.app",
+                "ServerApp",
+                "notebook==7.2.1",
+            ),
+            ("notebook@60", "notebook.app", "ServerApp", "notebook==60"),
+            (
+                "notebook@6",
+                "notebook.notebookapp",
+                "NotebookApp",
+                "notebook==6,setuptools",
+            ),
+            (
+                "notebook@6.5",
+                "notebook.notebookapp",
+                "NotebookApp",
+                "notebook==6.5,setuptools",
+            ),
+            (
+                "notebook==6.4.12",
+                "notebook.notebookapp",
+                "NotebookApp",
+                "notebook==6.4.12,setuptools",
+            ),
+            (
+                "notebook@6.*",
+                "notebook.notebookapp",
+                "NotebookApp",
+                "notebook==6.*,setuptools",
+            ),
+            (
+                "notebook@6.5.7rc1",
+                "notebook.notebookapp",
+                "NotebookApp",
+                "notebook==6.5.7rc1,setuptools",
+            ),
+            (
+                "nbclassic",
+                "nbclassic.notebookapp",
+                "ServerApp",
+                "nbclassic",
+            ),
+            (
+                "nbclassic@1.1",
+                "nbclassic.notebookapp",
+                "ServerApp",
+                "nbclassic==1.1",
+            ),
+        ];
+        for (specifier, main_import, server_app, with) in cases {
+            let runtime: Runtime = specifier.parse().unwrap();
+            assert_eq!(runtime.main_import(), main_import, "{specifier}");
+            assert_eq!(runtime.server_app(), server_app, "{specifier}");
+            assert_eq!(runtime.with_args().as_deref(), Some(with), "{specifier}");
+        }
+    }
+
+    #[test]
+    fn server_args_for_notebook_v6() {
+        let options = ServerOptions {
+            port: Some(8888),
+            token: Some("secret".to_string()),
+            no_browser: true,
+            ..ServerOptions::default()
+        };
+        let v6: Runtime = "notebook@6.5".parse().unwrap();
+        assert_eq!(
+            v6.server_args(&options),
+            [
+                "--NotebookApp.port=8888",
+                "--NotebookApp.token=secret",
+                "--NotebookApp.open_browser=False",
+            ]
+        );
+        assert_eq!(
+            v6.root_dir_arg(Path::new("/notebooks")),
+            "--NotebookApp.notebook_dir=/notebooks"
+        );
+        let v7: Runtime = "notebook@7".parse().unwrap();
+        assert_eq!(
+            v7.server_args(&options),
+            [
+                "--ServerApp.port=8888",
+                "--IdentityProvider.token=secret",
+                "--ServerApp.open_browser=False",
+            ]
+        );
+        assert_eq!(
+            v7.root_dir_arg(Path::new("/notebooks")),
+            "--ServerApp.root_dir=/notebooks"
+        );
+    }
+
+    #[test]
+    fn capture_script_quotes_the_mime_type() {
+        let script = prepare_capture_script(
+            Path::new("/notebooks/a.ipynb"),
+            None,
+            "python3",
+            r#"text/x-"quoted""#,
+            false,
+            Timeouts::default(),
+        );
+        assert!(script.contains(r#"    mime = "text/x-\"quoted\"""#));
+        assert!(script.contains(r#"print(f"error: The last cell has no `{mime}` output""#));
+        assert!(!script.contains(r#"`text/x-"quoted"`"#));
+    }
+
+    #[test]
+    fn invalid_runtimes() {
+        assert!("notebook@six".parse::<Runtime>().is_err());
+        assert!("voila".parse::<Runtime>().is_err());
+    }
+
+    #[test]
+    fn runtime_version_aliases() {
+        for specifier in ["lab@latest", "lab@stable", "lab"] {
+            let runtime: Runtime = specifier.parse().unwrap();
+            assert_eq!(
+                runtime.with_args().as_deref(),
+                Some("jupyterlab"),
+                "{specifier}"
+            );
+        }
+        for specifier in ["lab@", "lab@4.2;rm", "lab@>=4", "lab@v4", "lab@newest"] {
+            let err = specifier.parse::<Runtime>().unwrap_err();
+            assert!(
+                err.to_string().starts_with("Invalid version"),
+                "{specifier}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn runtime_requirements() {
+        let runtime: Runtime = "notebook@6".parse().unwrap();
+        assert_eq!(runtime.requirements(), ["notebook==6", "setuptools"]);
+        let runtime = Runtime::custom(CustomRuntime {
+            command: "jupyter-lab".to_string(),
+            args: vec![],
+            with: vec!["jupyterlab>=4,<5".to_string(), "ipykernel".to_string()],
+        });
+        // Entries are kept whole, even with commas in their specifiers
+        assert_eq!(runtime.requirements(), ["jupyterlab>=4,<5", "ipykernel"]);
+        let runtime = Runtime::custom(CustomRuntime {
+            command: "nteract".to_string(),
+            args: vec![],
+            with: vec![],
+        });
+        assert!(runtime.requirements().is_empty());
+        assert_eq!(runtime.with_args(), None);
+    }
+
+    #[test]
+    fn scripts_share_the_prelude() {
+        let path = Path::new("/notebooks/a.ipynb");
+        let prelude = main_prelude(path, Timeouts::default());
+        let scripts = [
+            prepare_exec_script(
+                path,
+                None,
+                "python3",
+                ExportFormat::Html,
+                Path::new("/notebooks/a.html"),
+                false,
+                Timeouts::default(),
+            ),
+            prepare_capture_script(
+                path,
+                None,
+                "python3",
+                "text/plain",
+                false,
+                Timeouts::default(),
+            ),
+            prepare_connect_script(path, Path::new("/kernel.json"), Timeouts::default()),
+        ];
+        for script in &scripts {
+            assert_eq!(script.matches(&prelude).count(), 1, "{script}");
+            assert_eq!(script.matches("def main():").count(), 1, "{script}");
+        }
+        // Only the connect script leaves starting a kernel to someone else
+        assert!(scripts[..2]
+            .iter()
+            .all(|script| script.contains("def execute():")));
+        assert!(!scripts[2].contains("NotebookClient"));
+    }
+}
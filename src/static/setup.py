@@ -5,29 +5,27 @@
 # of uv's from multiple virtual environments).
 
 
-def setup_merged_jupyter_environment():
-    """Setup Jupyter data directories and config paths from multiple virtual environments."""
+def setup_merged_jupyter_environment(cache_key):
+    """Setup Jupyter data directories and config paths from multiple virtual environments.
+
+    The merged data directory is cached under juv's data directory, keyed by `cache_key`
+    (provided by juv) and the data directories that contribute to it, so it is only rebuilt
+    when the set of environments changes.
+    """
+    import hashlib
     import os
-    import signal
+    import shutil
     import sys
     import tempfile
+    import time
     from pathlib import Path
 
     # jupyterlab, notebook, and nbclassic have this as a dependency
     from platformdirs import user_data_dir
 
     juv_data_dir = Path(user_data_dir("juv"))
-    juv_data_dir.mkdir(parents=True, exist_ok=True)
-
-    temp_dir = tempfile.TemporaryDirectory(dir=juv_data_dir)
-    merged_dir = Path(temp_dir.name)
-
-    def handle_termination(signum, frame):
-        temp_dir.cleanup()
-        sys.exit(0)
-
-    signal.signal(signal.SIGTERM, handle_termination)
-    signal.signal(signal.SIGINT, handle_termination)
+    cache_dir = juv_data_dir / "merged"
+    cache_dir.mkdir(parents=True, exist_ok=True)
 
     config_paths = []
     root_data_dir = Path(sys.prefix) / "share" / "jupyter"
@@ -43,24 +41,54 @@ def setup_merged_jupyter_environment():
 
         jupyter_paths.append(data_dir)
 
-    for path in reversed(jupyter_paths):
-        for item in path.rglob("*"):
-            if item.is_file():
-                dest = merged_dir / item.relative_to(path)
-                dest.parent.mkdir(parents=True, exist_ok=True)
-                try:
-                    os.link(item, dest)
-                except FileExistsError:
-                    pass
+    key = hashlib.sha256(cache_key.encode())
+    for path in jupyter_paths:
+        mtime = path.stat().st_mtime_ns if path.exists() else 0
+        key.update(f"\0{path}\0{mtime}".encode())
+    merged_dir = cache_dir / key.hexdigest()[:16]
+    marker = merged_dir / ".juv-complete"
+
+    if marker.exists():
+        marker.touch()
+    else:
+        # Build in a temporary directory and move it into place, so concurrent launches
+        # never see a partially merged directory
+        build_dir = Path(tempfile.mkdtemp(dir=cache_dir))
+        for path in reversed(jupyter_paths):
+            for item in path.rglob("*"):
+                if item.is_file():
+                    dest = build_dir / item.relative_to(path)
+                    dest.parent.mkdir(parents=True, exist_ok=True)
+                    try:
+                        os.link(item, dest)
+                    except FileExistsError:
+                        pass
+        (build_dir / marker.name).touch()
+        try:
+            build_dir.rename(merged_dir)
+        except OSError:
+            # Another launch finished the same merge first
+            shutil.rmtree(build_dir, ignore_errors=True)
+
+    # Drop merged directories (and abandoned builds) that haven't been used in a while
+    max_age = 30 * 24 * 60 * 60
+    for entry in cache_dir.iterdir():
+        if entry == merged_dir:
+            continue
+        try:
+            used = (entry / marker.name).stat().st_mtime
+        except OSError:
+            used = entry.stat().st_mtime
+        if time.time() - used > max_age:
+            shutil.rmtree(entry, ignore_errors=True)
 
     os.environ["JUPYTER_DATA_DIR"] = str(merged_dir)
     os.environ["JUPYTER_CONFIG_PATH"] = os.pathsep.join(map(str, config_paths))
 
 
-def setup():
+def setup(cache_key):
     """Setup the Jupyter environment. Called from the main script."""
 
-    setup_merged_jupyter_environment()
-
+    setup_merged_jupyter_environment(cache_key)
 
 ####################################################################################################